@@ -0,0 +1,146 @@
+//! The allocator abstraction used by the `_in` variants of the `plain` and `lazy` APIs.
+//!
+//! `RawAllocator` is deliberately minimal: it deals only in bytes and `Layout`s, leaving zero-sized
+//! types and overflow checking to the helpers in `plain` and `lazy`. `Global` forwards to the
+//! global heap, while `GlobalAdapter` lets any `GlobalAlloc` (such as `std::alloc::System`) be
+//! used.
+
+use std::alloc::{self, GlobalAlloc, Layout};
+use std::cmp;
+use std::ptr;
+
+/// A source of raw memory.
+///
+/// Layouts passed to an allocator by this crate never have a size of 0.
+///
+/// # Undefined Behaviour
+///
+/// Implementors must uphold the same contract as `GlobalAlloc`: memory returned by `alloc` must be
+/// valid for the requested layout until it is passed to `dealloc` or `realloc`, and `null` must be
+/// returned on failure.
+pub unsafe trait RawAllocator {
+    /// Allocates memory as described by `layout`. Returns `null` on OOM.
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8;
+
+    /// Deallocates the memory referenced by `ptr`, which must have been allocated by this
+    /// allocator with the given `layout`.
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout);
+
+    /// Like `alloc`, but the returned memory is zeroed.
+    ///
+    /// The default implementation calls `alloc` and zeroes the memory itself.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            ptr::write_bytes(ptr, 0, layout.size());
+        }
+        ptr
+    }
+
+    /// Resizes the allocation referenced by `ptr` to `new_size` bytes, preserving its contents up
+    /// to the smaller of the two sizes. Returns `null` on OOM, in which case the allocation is
+    /// unchanged.
+    ///
+    /// The default implementation allocates a new block, copies, and frees the old one.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+
+    /// Gets the number of bytes an allocation made with `layout` could actually use, which must be
+    /// at least `layout.size()`. Asking for that many bytes instead should cost no more memory.
+    ///
+    /// The default implementation returns `layout.size()`, which is all a `GlobalAlloc` can
+    /// promise.
+    fn usable_size(&self, layout: Layout) -> usize {
+        layout.size()
+    }
+}
+
+/// The global heap, as configured by `#[global_allocator]`.
+///
+/// `GlobalAlloc` has no way to ask how much slack an allocation of a given layout would get, so
+/// `usable_size` always returns the requested size, and the `_excess` functions never report any
+/// excess for `Global`. The same goes for `GlobalAdapter`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Global;
+
+unsafe impl RawAllocator for Global {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        alloc::dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        alloc::realloc(ptr, layout, new_size)
+    }
+}
+
+/// Adapts any `GlobalAlloc` into a `RawAllocator`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GlobalAdapter<A>(pub A);
+
+unsafe impl<A: GlobalAlloc> RawAllocator for GlobalAdapter<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.0.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.0.realloc(ptr, layout, new_size)
+    }
+}
+
+unsafe impl<A: RawAllocator + ?Sized> RawAllocator for &A {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        (**self).alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        (**self).alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        (**self).realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    fn usable_size(&self, layout: Layout) -> usize {
+        (**self).usable_size(layout)
+    }
+}
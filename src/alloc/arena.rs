@@ -0,0 +1,338 @@
+//! A bump allocator, for many short-lived allocations that can all be freed together.
+
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::mem::{align_of, size_of};
+use std::ptr::{self, NonNull};
+use super::allocator::{RawAllocator, Global};
+
+/// The size of the first chunk of an `Arena` made with `new`.
+const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// Hands out memory by bumping a pointer through large chunks, which are only freed when the arena
+/// is dropped.
+///
+/// Allocating is a handful of arithmetic operations unless the current chunk is full, in which
+/// case a new chunk at least twice as large as the last is allocated from the global heap.
+/// Individual allocations are never freed: `reset` and `rewind` make the memory handed out since
+/// some point available again all at once, and reuse the chunks already allocated.
+///
+/// The arena never reads, writes or drops any `T`. Values stored in it that need dropping must be
+/// dropped by the user before the memory is reset, rewound, or freed.
+///
+/// `Arena` is also a `RawAllocator`, so it can back the `_in` functions of `plain`, `lazy` and
+/// `checked`. Deallocating through it does nothing, and resizing happens in place only for the
+/// most recent allocation in the current chunk; anything else is copied to a fresh allocation.
+/// The chunks themselves bypass `plain`, so that with `debug-alloc` they don't clash with the
+/// records of allocations made from them at the same address.
+pub struct Arena {
+    chunks: RefCell<Vec<Chunk>>,
+    /// The index of the chunk being bumped through.
+    current: Cell<usize>,
+    /// The number of bytes used in the current chunk.
+    used: Cell<usize>,
+    /// The size of the next new chunk.
+    next_size: Cell<usize>,
+}
+
+struct Chunk {
+    ptr: *mut u8,
+    size: usize,
+}
+
+/// A position in an `Arena`, that it can later be rewound to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    chunk: usize,
+    used: usize,
+}
+
+unsafe impl Send for Arena {}
+
+impl Arena {
+    /// Makes a new arena without allocating.
+    pub fn new() -> Arena {
+        Arena::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Makes a new arena, without allocating, whose first chunk will hold `size` bytes.
+    pub fn with_chunk_size(size: usize) -> Arena {
+        Arena {
+            chunks: RefCell::new(Vec::new()),
+            current: Cell::new(0),
+            used: Cell::new(0),
+            next_size: Cell::new(cmp::max(size, 1)),
+        }
+    }
+
+    /// Allocates and returns a ptr to memory to store a single element of type T. Handles
+    /// zero-sized types automatically by returning a non-null dangling ptr. Returns `null` on OOM.
+    pub fn alloc<T>(&self) -> *mut T {
+        if size_of::<T>() == 0 {
+            NonNull::dangling().as_ptr()
+        } else {
+            self.alloc_bytes(size_of::<T>(), align_of::<T>()) as *mut T
+        }
+    }
+
+    /// Allocates and returns a raw slice of `len` elements of type T. Handles zero-sized types
+    /// and a `len` of 0 automatically by returning a non-null dangling ptr. The ptr is `null` on
+    /// OOM, or if the requested size overflows.
+    pub fn alloc_array<T>(&self, len: usize) -> *mut [T] {
+        let ptr = if size_of::<T>() == 0 || len == 0 {
+            NonNull::dangling().as_ptr()
+        } else {
+            match size_of::<T>().checked_mul(len) {
+                Some(size) if size <= isize::MAX as usize => {
+                    self.alloc_bytes(size, align_of::<T>()) as *mut T
+                }
+                _ => ptr::null_mut(),
+            }
+        };
+        ptr::slice_from_raw_parts_mut(ptr, len)
+    }
+
+    /// Gets the current position of the arena, to `rewind` to later.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint { chunk: self.current.get(), used: self.used.get() }
+    }
+
+    /// Makes all the memory handed out since `checkpoint` was taken available again. Ptrs into
+    /// that memory, and checkpoints taken after `checkpoint`, must not be used afterwards.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        debug_assert!((checkpoint.chunk, checkpoint.used) <= (self.current.get(), self.used.get()),
+                      "rewind to a checkpoint that is ahead of the arena");
+        self.current.set(checkpoint.chunk);
+        self.used.set(checkpoint.used);
+    }
+
+    /// Makes all the memory handed out available again, keeping the chunks already allocated for
+    /// reuse. Ptrs into the arena and its checkpoints must not be used afterwards.
+    pub fn reset(&mut self) {
+        self.current.set(0);
+        self.used.set(0);
+    }
+
+    /// Gets the total size of the chunks the arena has allocated.
+    pub fn capacity(&self) -> usize {
+        self.chunks.borrow().iter().map(|c| c.size).sum()
+    }
+
+    /// Bumps through the current chunk, or moves on to a later one. `size` is non-zero.
+    fn alloc_bytes(&self, size: usize, align: usize) -> *mut u8 {
+        let mut chunks = self.chunks.borrow_mut();
+        if let Some(chunk) = chunks.get(self.current.get()) {
+            if let Some(ptr) = bump(chunk, self.used.get(), size, align, &self.used) {
+                return ptr;
+            }
+        }
+
+        // Skip over chunks kept by `reset` or `rewind` while they're too small, and allocate a
+        // new one after the current chunk if none fit.
+        let mut index = if chunks.is_empty() { 0 } else { self.current.get() + 1 };
+        while let Some(chunk) = chunks.get(index) {
+            if let Some(ptr) = bump(chunk, 0, size, align, &self.used) {
+                self.current.set(index);
+                return ptr;
+            }
+            index += 1;
+        }
+        let index = cmp::min(self.current.get() + 1, chunks.len());
+
+        let chunk_size = match size.checked_add(align - 1) {
+            Some(needed) => cmp::max(self.next_size.get(), needed),
+            None => return ptr::null_mut(),
+        };
+        let chunk_ptr = match Layout::from_size_align(chunk_size, 1) {
+            Ok(layout) => unsafe { Global.alloc(layout) },
+            Err(_) => ptr::null_mut(),
+        };
+        if chunk_ptr.is_null() {
+            return chunk_ptr;
+        }
+        self.next_size.set(chunk_size.saturating_mul(2));
+        chunks.insert(index, Chunk { ptr: chunk_ptr, size: chunk_size });
+        self.current.set(index);
+        bump(&chunks[index], 0, size, align, &self.used).unwrap()
+    }
+}
+
+/// Carves `size` bytes aligned to `align` out of `chunk` after its first `used` bytes, and records
+/// the new number of used bytes. Returns `None` if they don't fit.
+fn bump(chunk: &Chunk, used: usize, size: usize, align: usize, used_out: &Cell<usize>)
+        -> Option<*mut u8> {
+    let start = chunk.ptr as usize + used;
+    let offset = start.checked_add(align - 1)? & !(align - 1);
+    let end = (offset - chunk.ptr as usize).checked_add(size)?;
+    if end > chunk.size {
+        return None;
+    }
+    used_out.set(end);
+    Some(chunk.ptr.wrapping_add(offset - chunk.ptr as usize))
+}
+
+unsafe impl RawAllocator for Arena {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_bytes(layout.size(), layout.align())
+    }
+
+    unsafe fn dealloc(&self, _: *mut u8, _: Layout) {}
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        {
+            let chunks = self.chunks.borrow();
+            if let Some(chunk) = chunks.get(self.current.get()) {
+                // The most recent allocation ends exactly where the used part of the chunk does.
+                let offset = (ptr as usize).wrapping_sub(chunk.ptr as usize);
+                if offset < chunk.size && offset + layout.size() == self.used.get()
+                   && new_size <= chunk.size - offset {
+                    self.used.set(offset + new_size);
+                    return ptr;
+                }
+            }
+        }
+        if new_size <= layout.size() {
+            // The memory past `new_size` is simply never used again.
+            return ptr;
+        }
+        let new_ptr = self.alloc_bytes(new_size, layout.align());
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size());
+        }
+        new_ptr
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for chunk in self.chunks.get_mut().drain(..) {
+            unsafe { Global.dealloc(chunk.ptr, Layout::from_size_align_unchecked(chunk.size, 1)) }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::{lazy, plain};
+    use crate::rawptr::{RawPtrExt, RawMutPtrExt};
+    use crate::rawslice::{RawSlice, RawMutSlice};
+
+    #[test]
+    fn test_alloc() {
+        let arena = Arena::with_chunk_size(64);
+        unsafe {
+            let a = arena.alloc::<u8>();
+            let b = arena.alloc::<u64>();
+            let c = arena.alloc::<u16>();
+            assert_eq!(b as usize % align_of::<u64>(), 0);
+            *a = 1;
+            *b = 2;
+            *c = 3;
+            assert_eq!((*a, *b, *c), (1, 2, 3));
+        }
+        assert_eq!(arena.capacity(), 64);
+    }
+
+    #[test]
+    fn test_alloc_array() {
+        let arena = Arena::with_chunk_size(16);
+        unsafe {
+            let a = arena.alloc_array::<u32>(3);
+            let b = arena.alloc_array::<u32>(100);
+            assert_eq!(a.len(), 3);
+            assert_eq!(b.len(), 100);
+            for i in 0..100 {
+                RawMutSlice::write(b, i, i as u32);
+            }
+            for i in 0..3 {
+                RawMutSlice::write(a, i, 7);
+            }
+            assert!((0..100).all(|i| RawSlice::read(b, i) == i as u32));
+            assert!((0..3).all(|i| RawSlice::read(a, i) == 7));
+        }
+        // The second array didn't fit in the first chunk, so got one to itself.
+        assert_eq!(arena.capacity(), 16 + 403);
+    }
+
+    #[test]
+    fn test_zst_and_overflow() {
+        let arena = Arena::new();
+        assert_eq!(arena.alloc::<()>(), NonNull::dangling().as_ptr());
+        let zst = arena.alloc_array::<()>(usize::MAX);
+        assert_eq!((zst as *mut (), zst.len()), (NonNull::dangling().as_ptr(), usize::MAX));
+        let empty = arena.alloc_array::<u64>(0);
+        assert_eq!(empty as *mut u64, NonNull::dangling().as_ptr());
+        assert!(arena.alloc_array::<u64>(usize::MAX / 4).is_null());
+        assert!(arena.alloc_array::<u8>(usize::MAX).is_null());
+        assert_eq!(arena.capacity(), 0);
+    }
+
+    #[test]
+    fn test_checkpoint_rewind() {
+        let mut arena = Arena::with_chunk_size(32);
+        let a = arena.alloc_array::<u8>(8) as *mut u8;
+        let checkpoint = arena.checkpoint();
+        let b = arena.alloc_array::<u8>(8) as *mut u8;
+        // Spill into a second chunk.
+        let c = arena.alloc_array::<u8>(32) as *mut u8;
+        arena.rewind(checkpoint);
+        assert_eq!(arena.alloc_array::<u8>(8) as *mut u8, b);
+        assert_eq!(arena.alloc_array::<u8>(32) as *mut u8, c);
+        assert_eq!(arena.capacity(), 32 + 64);
+
+        arena.reset();
+        assert_eq!(arena.alloc_array::<u8>(8) as *mut u8, a);
+        assert_eq!(arena.capacity(), 32 + 64);
+    }
+
+    #[test]
+    fn test_reset_reuses_later_chunks() {
+        let mut arena = Arena::with_chunk_size(8);
+        arena.alloc_array::<u8>(8);
+        let big = arena.alloc_array::<u8>(100) as *mut u8;
+        arena.reset();
+        arena.alloc_array::<u8>(8);
+        assert_eq!(arena.alloc_array::<u8>(50) as *mut u8, big);
+        // Nothing kept is big enough, so a new chunk goes after the current one.
+        arena.alloc_array::<u8>(1000);
+        assert_eq!(arena.capacity(), 8 + 100 + 1000);
+    }
+
+    #[test]
+    fn test_raw_allocator() {
+        let arena = Arena::new();
+        unsafe {
+            let a = plain::alloc_array_in::<u32, _>(&arena, 4);
+            for i in 0..4 {
+                RawMutPtrExt::write(RawPtrExt::add(a, i), i as u32);
+            }
+            // The most recent allocation grows and shrinks in place.
+            assert_eq!(plain::realloc_array_in(&arena, a, 4, 8), a);
+            assert_eq!(plain::realloc_array_in(&arena, a, 8, 6), a);
+            let b = plain::alloc_in::<u64, _>(&arena);
+            assert_eq!(plain::realloc_array_in(&arena, a, 6, 2), a);
+
+            // Anything else moves when it grows.
+            let c = plain::realloc_array_in(&arena, a, 2, 10);
+            assert!(c != a && !c.is_null());
+            assert!((0..2).all(|i| RawPtrExt::read(RawPtrExt::add(c, i)) == i as u32));
+            plain::dealloc_array_in(&arena, c, 10);
+            plain::dealloc_in(&arena, b);
+
+            let z = lazy::alloc_array_zeroed_in::<u8, _>(&arena, 100);
+            assert!((0..100).all(|i| RawPtrExt::read(RawPtrExt::add(z, i)) == 0));
+            lazy::dealloc_array_in(&arena, z, 100);
+        }
+        assert_eq!(arena.capacity(), DEFAULT_CHUNK_SIZE);
+    }
+}
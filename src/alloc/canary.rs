@@ -0,0 +1,159 @@
+//! The canaries behind the `debug-canary` feature.
+//!
+//! Every allocation made through `plain` (and so through `lazy` and `checked`) is padded with
+//! canary bytes on both sides of the region handed out. Calls that resize or free an allocation
+//! verify the canaries first, and panic with the offset of the first corrupted byte, so writes
+//! that ran just past either end are caught, if somewhat after the fact. This is much cheaper
+//! than `guard::GuardPage`, and works with any allocator.
+//!
+//! Without the feature, allocations are left unpadded, and all of these functions compile to
+//! nothing.
+
+#[cfg(feature = "debug-canary")]
+pub use self::imp::*;
+
+#[cfg(not(feature = "debug-canary"))]
+pub use self::noop::*;
+
+#[cfg(feature = "debug-canary")]
+mod imp {
+    use std::alloc::Layout;
+    use std::cmp;
+    use std::ptr;
+
+    /// The byte the padding is filled with.
+    const CANARY: u8 = 0xFD;
+
+    /// The number of canary bytes on either side of an allocation.
+    const CANARY_LEN: usize = 16;
+
+    /// The padding before an allocation, which must keep it aligned.
+    fn front(align: usize) -> usize {
+        cmp::max(CANARY_LEN, align)
+    }
+
+    /// Computes the layout to actually request from the allocator for `layout`. Returns `None` on
+    /// overflow.
+    pub fn padded_layout(layout: Layout) -> Option<Layout> {
+        let size = layout.size().checked_add(front(layout.align()))?.checked_add(CANARY_LEN)?;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
+
+    /// Fills in the canaries of a new allocation at `raw`, made with `padded_layout(layout)`, and
+    /// returns the ptr to hand out. `null` ptrs are passed through.
+    pub unsafe fn on_alloc(raw: *mut u8, layout: Layout) -> *mut u8 {
+        if raw.is_null() {
+            return raw;
+        }
+        let front = front(layout.align());
+        ptr::write_bytes(raw, CANARY, front);
+        ptr::write_bytes(raw.add(front + layout.size()), CANARY, CANARY_LEN);
+        raw.add(front)
+    }
+
+    /// Checks the canaries around `ptr`, an allocation of `len` elements with the given layout,
+    /// panicking if any were overwritten. Returns the ptr the allocator actually handed out. `op`
+    /// names the function being checked.
+    pub unsafe fn verify(op: &str, ptr: *mut u8, layout: Layout, len: usize) -> *mut u8 {
+        let front = front(layout.align()) as isize;
+        let size = layout.size() as isize;
+        let corrupted = (-front..0).chain(size..size + CANARY_LEN as isize)
+            .find(|&offset| *ptr.offset(offset) != CANARY);
+        if let Some(offset) = corrupted {
+            panic!("{}: ptr {:p} with len {} has a corrupted canary at byte offset {}",
+                   op, ptr, len, offset);
+        }
+        ptr.sub(front as usize)
+    }
+}
+
+#[cfg(not(feature = "debug-canary"))]
+#[allow(unused_variables)]
+mod noop {
+    use std::alloc::Layout;
+
+    #[inline(always)]
+    pub fn padded_layout(layout: Layout) -> Option<Layout> {
+        Some(layout)
+    }
+
+    #[inline(always)]
+    pub unsafe fn on_alloc(raw: *mut u8, layout: Layout) -> *mut u8 {
+        raw
+    }
+
+    #[inline(always)]
+    pub unsafe fn verify(op: &str, ptr: *mut u8, layout: Layout, len: usize) -> *mut u8 {
+        ptr
+    }
+}
+
+
+
+#[cfg(all(test, feature = "debug-canary"))]
+mod test {
+    use crate::alloc::plain::*;
+    use crate::alloc::test::panic_message;
+    use crate::uncheckedslice::SliceUncheckedExt;
+
+    #[test]
+    fn test_overrun() {
+        unsafe {
+            let x = alloc_array::<u32>(4);
+            let slice = std::slice::from_raw_parts_mut(x, 5);
+            *slice.as_unchecked_mut().get_mut(4) = 1;
+            let msg = panic_message(|| dealloc_array(x, 4));
+            assert_eq!(msg, format!("dealloc_array: ptr {:p} with len 4 has a corrupted canary \
+                                     at byte offset 16", x));
+        }
+    }
+
+    #[test]
+    fn test_underrun() {
+        unsafe {
+            let x = alloc_array::<u8>(3);
+            *x.sub(1) = 0;
+            let msg = panic_message(|| { realloc_array(x, 3, 6); });
+            assert_eq!(msg, format!("realloc_array: ptr {:p} with len 3 has a corrupted canary \
+                                     at byte offset -1", x));
+        }
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    fn test_corrupt_stays_live() {
+        unsafe {
+            let x = alloc_array::<u8>(2);
+            *x.add(2) = 0;
+            assert!(panic_message(|| { realloc_array(x, 2, 4); }).contains("corrupted canary"));
+            assert!(panic_message(|| dealloc_array(x, 2)).contains("corrupted canary"));
+            // Neither panic released the ptr, so once repaired it can still be freed.
+            *x.add(2) = 0xFD;
+            dealloc_array(x, 2);
+        }
+    }
+
+    #[test]
+    fn test_intact() {
+        unsafe {
+            let x = alloc_array::<u16>(4);
+            for i in 0..4 {
+                *x.add(i) = i as u16;
+            }
+            let x = realloc_array(x, 4, 100);
+            *x.add(99) = 1;
+            let x = realloc_array(x, 100, 2);
+            assert_eq!((*x, *x.add(1)), (0, 1));
+            dealloc_array(x, 2);
+
+            let x = alloc_array_zeroed::<u64>(3);
+            assert_eq!(*(x as *const [u64; 3]), [0; 3]);
+            dealloc_array(x, 3);
+
+            let x = alloc_array_aligned::<u8>(5, 64);
+            assert_eq!(x as usize % 64, 0);
+            *x.add(4) = 1;
+            dealloc_array_aligned(x, 5, 64);
+        }
+    }
+}
@@ -0,0 +1,702 @@
+use std::alloc::Layout;
+use std::error::Error;
+use std::fmt;
+use std::mem::size_of;
+use std::ptr::NonNull;
+use super::allocator::{RawAllocator, Global};
+use super::plain;
+
+/// The ways an allocation request can fail.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllocError {
+    /// The requested number of elements does not fit in the address space.
+    CapacityOverflow {
+        /// The size of a single element, in bytes.
+        elem_size: usize,
+        /// The number of elements requested.
+        len: usize,
+    },
+    /// The allocator could not satisfy a request of the given layout.
+    OutOfMemory {
+        /// The layout that was requested.
+        layout: Layout,
+    },
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocError::CapacityOverflow { elem_size, len } => {
+                write!(f, "capacity overflow allocating {} elements of {} bytes", len, elem_size)
+            }
+            AllocError::OutOfMemory { layout } => {
+                write!(f, "out of memory allocating {} bytes with alignment {}",
+                       layout.size(), layout.align())
+            }
+        }
+    }
+}
+
+impl Error for AllocError {}
+
+/// Computes the layout of `len` elements of type T, or reports a capacity overflow.
+#[inline]
+fn array_layout<T>(len: usize) -> Result<Layout, AllocError> {
+    Layout::array::<T>(len).map_err(|_| AllocError::CapacityOverflow {
+        elem_size: size_of::<T>(),
+        len,
+    })
+}
+
+/// Computes the layout of `len` elements of type T, aligned to at least `align`, or reports a
+/// capacity overflow.
+#[inline]
+fn aligned_layout<T>(len: usize, align: usize) -> Result<Layout, AllocError> {
+    plain::aligned_layout::<T>(len, align).ok_or(AllocError::CapacityOverflow {
+        elem_size: size_of::<T>(),
+        len,
+    })
+}
+
+/// Computes the layout of a header of type H followed by `len` elements of type T, or reports a
+/// capacity overflow.
+#[inline]
+fn tail_layout<H, T>(len: usize) -> Result<Layout, AllocError> {
+    match plain::tail_layout::<H, T>(len) {
+        Some((layout, _)) => Ok(layout),
+        None => Err(AllocError::CapacityOverflow { elem_size: size_of::<T>(), len }),
+    }
+}
+
+/// Turns a `null` ptr returned by `plain` into an `OutOfMemory` error for the given layout.
+#[inline]
+fn non_null<T>(ptr: *mut T, layout: Layout) -> Result<NonNull<T>, AllocError> {
+    NonNull::new(ptr).ok_or(AllocError::OutOfMemory { layout })
+}
+
+/// Allocates and returns a ptr to memory to store a single element of type T. Handles zero-sized
+/// types automatically by returning a non-null dangling ptr.
+///
+/// # Errors
+///
+/// Yields `OutOfMemory` if the allocator fails.
+#[inline]
+pub unsafe fn alloc<T>() -> Result<NonNull<T>, AllocError> {
+    alloc_in(&Global)
+}
+
+/// Like `alloc`, but allocates from the given allocator.
+#[inline]
+pub unsafe fn alloc_in<T, A: RawAllocator + ?Sized>(a: &A) -> Result<NonNull<T>, AllocError> {
+    if size_of::<T>() == 0 {
+        Ok(NonNull::dangling())
+    } else {
+        let layout = Layout::new::<T>();
+        NonNull::new(plain::alloc_in::<T, A>(a)).ok_or(AllocError::OutOfMemory { layout })
+    }
+}
+
+/// Allocates and returns a ptr to memory to store a `len` elements of type T. Handles zero-sized
+/// types automatically by returning a non-null dangling ptr.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array<T>(len: usize) -> Result<NonNull<T>, AllocError> {
+    alloc_array_in(&Global, len)
+}
+
+/// Like `alloc_array`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize)
+        -> Result<NonNull<T>, AllocError> {
+    debug_assert!(len != 0, "0 len passed to alloc_array");
+    if size_of::<T>() == 0 {
+        Ok(NonNull::dangling())
+    } else {
+        let layout = array_layout::<T>(len)?;
+        NonNull::new(plain::alloc_array_in::<T, A>(a, len))
+            .ok_or(AllocError::OutOfMemory { layout })
+    }
+}
+
+/// Resizes the allocation referenced by `ptr` to fit `len` elements of type T. Handles zero-sized
+/// types automatically by returning the given ptr. `old_len` must be then `len` provided to the
+/// call to `alloc_array` or `realloc_array` that created `ptr`.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails. In either case the original allocation is unchanged.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array<T>(ptr: NonNull<T>, old_len: usize, len: usize)
+        -> Result<NonNull<T>, AllocError> {
+    realloc_array_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>,
+                                                             old_len: usize, len: usize)
+        -> Result<NonNull<T>, AllocError> {
+    debug_assert!(len != 0, "0 len passed to realloc_array");
+    if size_of::<T>() == 0 {
+        Ok(ptr)
+    } else {
+        let layout = array_layout::<T>(len)?;
+        NonNull::new(plain::realloc_array_in(a, ptr.as_ptr(), old_len, len))
+            .ok_or(AllocError::OutOfMemory { layout })
+    }
+}
+
+/// Like `alloc_array`, but also returns the number of elements that actually fit in the
+/// allocation, which is at least `len`. See `plain::alloc_array_excess`.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_excess<T>(len: usize) -> Result<(NonNull<T>, usize), AllocError> {
+    alloc_array_excess_in(&Global, len)
+}
+
+/// Like `alloc_array_excess`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_excess_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize)
+        -> Result<(NonNull<T>, usize), AllocError> {
+    let layout = array_layout::<T>(len)?;
+    let (ptr, excess) = plain::alloc_array_excess_in::<T, A>(a, len);
+    Ok((non_null(ptr, layout)?, excess))
+}
+
+/// Like `realloc_array`, but also returns the number of elements that actually fit in the new
+/// allocation, which is at least `len`. See `plain::alloc_array_excess`.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails. In either case the original allocation is unchanged.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_excess<T>(ptr: NonNull<T>, old_len: usize, len: usize)
+        -> Result<(NonNull<T>, usize), AllocError> {
+    realloc_array_excess_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array_excess`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_excess_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>,
+                                                                   old_len: usize, len: usize)
+        -> Result<(NonNull<T>, usize), AllocError> {
+    let layout = array_layout::<T>(len)?;
+    let (ptr, excess) = plain::realloc_array_excess_in(a, ptr.as_ptr(), old_len, len);
+    Ok((non_null(ptr, layout)?, excess))
+}
+
+/// Like `alloc`, but the returned memory is zeroed.
+///
+/// # Errors
+///
+/// Yields `OutOfMemory` if the allocator fails.
+#[inline]
+pub unsafe fn alloc_zeroed<T>() -> Result<NonNull<T>, AllocError> {
+    alloc_zeroed_in(&Global)
+}
+
+/// Like `alloc_zeroed`, but allocates from the given allocator.
+#[inline]
+pub unsafe fn alloc_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A)
+        -> Result<NonNull<T>, AllocError> {
+    non_null(plain::alloc_zeroed_in::<T, A>(a), Layout::new::<T>())
+}
+
+/// Like `alloc_array`, but the returned memory is zeroed. See `plain::alloc_array_zeroed`.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_zeroed<T>(len: usize) -> Result<NonNull<T>, AllocError> {
+    alloc_array_zeroed_in(&Global, len)
+}
+
+/// Like `alloc_array_zeroed`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize)
+        -> Result<NonNull<T>, AllocError> {
+    let layout = array_layout::<T>(len)?;
+    non_null(plain::alloc_array_zeroed_in::<T, A>(a, len), layout)
+}
+
+/// Like `realloc_array`, but if the allocation grows, the elements past `old_len` are zeroed. The
+/// first `old_len` elements are preserved as usual.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails. In either case the original allocation is unchanged.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_zeroed<T>(ptr: NonNull<T>, old_len: usize, len: usize)
+        -> Result<NonNull<T>, AllocError> {
+    realloc_array_zeroed_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array_zeroed`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>,
+                                                                    old_len: usize, len: usize)
+        -> Result<NonNull<T>, AllocError> {
+    let layout = array_layout::<T>(len)?;
+    non_null(plain::realloc_array_zeroed_in(a, ptr.as_ptr(), old_len, len), layout)
+}
+
+/// Tries to grow the allocation referenced by `ptr` in-place to fit `len` elements of type `T`.
+/// If successful, yields `Ok`. If unsuccessful, yields `Err`, and the allocation is unchanged.
+/// Handles zero-sized types by always returning `Ok`.
+///
+/// See `plain::try_grow_inplace` for when this can succeed.
+///
+/// # Undefined Behaviour
+///
+/// * `old_len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+/// * `len` must not be 0.
+/// * `len` must not be smaller than `old_len`.
+#[inline]
+pub unsafe fn try_grow_inplace<T>(ptr: NonNull<T>, old_len: usize, len: usize) -> Result<(), ()> {
+    plain::try_grow_inplace(ptr.as_ptr(), old_len, len)
+}
+
+/// Tries to shrink the allocation referenced by `ptr` in-place to fit `len` elements of type `T`.
+/// If successful, yields `Ok`. If unsuccessful, yields `Err`, and the allocation is unchanged.
+/// Handles zero-sized types by always returning `Ok`.
+///
+/// See `plain::try_shrink_inplace` for when this can succeed.
+///
+/// # Undefined Behaviour
+///
+/// * `old_len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+/// * `len` must not be 0.
+/// * `len` must not be larger than `old_len`.
+#[inline]
+pub unsafe fn try_shrink_inplace<T>(ptr: NonNull<T>, old_len: usize, len: usize)
+        -> Result<(), ()> {
+    plain::try_shrink_inplace(ptr.as_ptr(), old_len, len)
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with `alloc`.
+/// Handles zero-sized types automatically by doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc` method.
+/// * The `ptr` must not have been previously deallocated.
+#[inline]
+pub unsafe fn dealloc<T>(ptr: NonNull<T>) {
+    plain::dealloc(ptr.as_ptr());
+}
+
+/// Like `dealloc`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_in` method with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+#[inline]
+pub unsafe fn dealloc_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>) {
+    plain::dealloc_in(a, ptr.as_ptr());
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with `alloc_array` or
+/// `realloc_array`. Handles zero-sized types automatically by doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array` or `realloc_array` methods.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array<T>(ptr: NonNull<T>, len: usize) {
+    plain::dealloc_array(ptr.as_ptr(), len);
+}
+
+/// Like `dealloc_array`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_in` or `realloc_array_in`
+///   methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>, len: usize) {
+    plain::dealloc_array_in(a, ptr.as_ptr(), len);
+}
+
+/// Allocates and returns a ptr to memory to store `len` elements of type T, aligned to at least
+/// `align` bytes. Handles zero-sized types automatically by returning a non-null dangling ptr with
+/// that alignment.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+/// * `align` must be a power of two.
+#[inline]
+pub unsafe fn alloc_array_aligned<T>(len: usize, align: usize)
+        -> Result<NonNull<T>, AllocError> {
+    alloc_array_aligned_in(&Global, len, align)
+}
+
+/// Like `alloc_array_aligned`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+/// * `align` must be a power of two.
+#[inline]
+pub unsafe fn alloc_array_aligned_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize,
+                                                                   align: usize)
+        -> Result<NonNull<T>, AllocError> {
+    let layout = aligned_layout::<T>(len, align)?;
+    non_null(plain::alloc_array_aligned_in::<T, A>(a, len, align), layout)
+}
+
+/// Resizes the allocation referenced by `ptr` to fit `len` elements of type T, keeping its
+/// alignment. Handles zero-sized types automatically by returning the given ptr. `old_len` and
+/// `align` must be the ones provided to the call to `alloc_array_aligned` or
+/// `realloc_array_aligned` that created `ptr`.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if `len` elements of T do not fit in the address space, and
+/// `OutOfMemory` if the allocator fails. In either case the original allocation is unchanged.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_aligned<T>(ptr: NonNull<T>, old_len: usize, len: usize,
+                                       align: usize) -> Result<NonNull<T>, AllocError> {
+    realloc_array_aligned_in(&Global, ptr, old_len, len, align)
+}
+
+/// Like `realloc_array_aligned`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_aligned_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>,
+                                                                     old_len: usize, len: usize,
+                                                                     align: usize)
+        -> Result<NonNull<T>, AllocError> {
+    let layout = aligned_layout::<T>(len, align)?;
+    non_null(plain::realloc_array_aligned_in(a, ptr.as_ptr(), old_len, len, align), layout)
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with
+/// `alloc_array_aligned` or `realloc_array_aligned`. Handles zero-sized types automatically by
+/// doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_aligned` or
+///   `realloc_array_aligned` methods.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` and `align` must be the ones provided to the last successful allocator call that
+///   created or changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_aligned<T>(ptr: NonNull<T>, len: usize, align: usize) {
+    plain::dealloc_array_aligned(ptr.as_ptr(), len, align);
+}
+
+/// Like `dealloc_array_aligned`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_aligned_in` or
+///   `realloc_array_aligned_in` methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` and `align` must be the ones provided to the last successful allocator call that
+///   created or changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_aligned_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<T>,
+                                                                     len: usize, align: usize) {
+    plain::dealloc_array_aligned_in(a, ptr.as_ptr(), len, align);
+}
+
+/// Allocates and returns a ptr to memory to store a header of type H followed by `len` elements of
+/// type T, with any padding needed to align the elements. Returns the ptr to the header and a raw
+/// slice covering the elements. If both parts are zero-sized, the allocator is not touched and a
+/// non-null dangling ptr is returned.
+///
+/// Unlike `alloc_array`, `len` may be 0.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if the header and `len` elements of T do not fit in the address
+/// space, and `OutOfMemory` if the allocator fails.
+#[inline]
+pub unsafe fn alloc_with_tail<H, T>(len: usize) -> Result<(NonNull<H>, *mut [T]), AllocError> {
+    alloc_with_tail_in(&Global, len)
+}
+
+/// Like `alloc_with_tail`, but allocates from the given allocator.
+#[inline]
+pub unsafe fn alloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, len: usize)
+        -> Result<(NonNull<H>, *mut [T]), AllocError> {
+    let layout = tail_layout::<H, T>(len)?;
+    let (ptr, tail) = plain::alloc_with_tail_in::<H, T, A>(a, len);
+    Ok((non_null(ptr, layout)?, tail))
+}
+
+/// Resizes the allocation referenced by `ptr` to fit a header of type H followed by `len` elements
+/// of type T. The header and the first `min(old_len, len)` elements are preserved. `old_len` must
+/// be the `len` provided to the call to `alloc_with_tail` or `realloc_with_tail` that created
+/// `ptr`.
+///
+/// # Errors
+///
+/// Yields `CapacityOverflow` if the header and `len` elements of T do not fit in the address
+/// space, and `OutOfMemory` if the allocator fails. In either case the original allocation is
+/// unchanged.
+#[inline]
+pub unsafe fn realloc_with_tail<H, T>(ptr: NonNull<H>, old_len: usize, len: usize)
+        -> Result<(NonNull<H>, *mut [T]), AllocError> {
+    realloc_with_tail_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_with_tail`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+#[inline]
+pub unsafe fn realloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<H>,
+                                                                    old_len: usize, len: usize)
+        -> Result<(NonNull<H>, *mut [T]), AllocError> {
+    let layout = tail_layout::<H, T>(len)?;
+    let (ptr, tail) = plain::realloc_with_tail_in::<H, T, A>(a, ptr.as_ptr(), old_len, len);
+    Ok((non_null(ptr, layout)?, tail))
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with `alloc_with_tail` or
+/// `realloc_with_tail`. Handles zero-sized layouts automatically by doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_with_tail` or `realloc_with_tail`
+///   methods.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_with_tail<H, T>(ptr: NonNull<H>, len: usize) {
+    plain::dealloc_with_tail::<H, T>(ptr.as_ptr(), len);
+}
+
+/// Like `dealloc_with_tail`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_with_tail_in` or
+///   `realloc_with_tail_in` methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, ptr: NonNull<H>,
+                                                                    len: usize) {
+    plain::dealloc_with_tail_in::<H, T, A>(a, ptr.as_ptr(), len);
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::Layout;
+    use std::ptr;
+
+    /// An allocator that is always out of memory.
+    struct Exhausted;
+
+    unsafe impl RawAllocator for Exhausted {
+        unsafe fn alloc(&self, _: Layout) -> *mut u8 {
+            ptr::null_mut()
+        }
+
+        unsafe fn dealloc(&self, _: *mut u8, _: Layout) {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_alloc() {
+        unsafe {
+            let x = alloc_array::<u32>(4).unwrap();
+            *x.as_ptr() = 3;
+            let x = realloc_array(x, 4, 8).unwrap();
+            assert_eq!(*x.as_ptr(), 3);
+            dealloc_array(x, 8);
+
+            let y = alloc::<u8>().unwrap();
+            dealloc(y);
+
+            let z = alloc_array::<()>(usize::MAX).unwrap();
+            assert_eq!(realloc_array(z, usize::MAX, 1), Ok(z));
+            dealloc_array(z, 1);
+        }
+    }
+
+    #[test]
+    fn test_capacity_overflow() {
+        unsafe {
+            assert_eq!(alloc_array::<u64>(usize::MAX),
+                       Err(AllocError::CapacityOverflow { elem_size: 8, len: usize::MAX }));
+
+            let x = alloc_array::<u16>(2).unwrap();
+            assert_eq!(realloc_array(x, 2, usize::MAX),
+                       Err(AllocError::CapacityOverflow { elem_size: 2, len: usize::MAX }));
+            dealloc_array(x, 2);
+
+            // Overflow is detected before the allocator is consulted.
+            assert_eq!(alloc_array_in::<u32, _>(&Exhausted, usize::MAX / 2),
+                       Err(AllocError::CapacityOverflow { elem_size: 4, len: usize::MAX / 2 }));
+        }
+    }
+
+    #[test]
+    fn test_out_of_memory() {
+        unsafe {
+            assert_eq!(alloc_in::<u64, _>(&Exhausted),
+                       Err(AllocError::OutOfMemory { layout: Layout::new::<u64>() }));
+            assert_eq!(alloc_array_in::<u32, _>(&Exhausted, 10),
+                       Err(AllocError::OutOfMemory { layout: Layout::new::<[u32; 10]>() }));
+            assert!(alloc_array_in::<(), _>(&Exhausted, 10).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_variants() {
+        unsafe {
+            let x = alloc_array_zeroed::<u32>(4).unwrap();
+            assert_eq!(*x.as_ptr().add(3), 0);
+            let x = realloc_array_zeroed(x, 4, 8).unwrap();
+            assert_eq!(*x.as_ptr().add(7), 0);
+            let (x, len) = realloc_array_excess(x, 8, 12).unwrap();
+            assert!(len >= 12);
+            dealloc_array(x, len);
+
+            let y = alloc_array_aligned::<u8>(3, 64).unwrap();
+            assert_eq!(y.as_ptr() as usize % 64, 0);
+            let y = realloc_array_aligned(y, 3, 100, 64).unwrap();
+            assert_eq!(y.as_ptr() as usize % 64, 0);
+            dealloc_array_aligned(y, 100, 64);
+
+            let (h, tail) = alloc_with_tail::<u64, u8>(3).unwrap();
+            assert_eq!(tail.len(), 3);
+            let (h, tail) = realloc_with_tail::<u64, u8>(h, 3, 5).unwrap();
+            assert_eq!(tail.len(), 5);
+            dealloc_with_tail::<u64, u8>(h, 5);
+        }
+    }
+
+    #[test]
+    fn test_variant_errors() {
+        unsafe {
+            assert_eq!(alloc_zeroed_in::<u64, _>(&Exhausted),
+                       Err(AllocError::OutOfMemory { layout: Layout::new::<u64>() }));
+            assert_eq!(alloc_array_zeroed::<u64>(usize::MAX),
+                       Err(AllocError::CapacityOverflow { elem_size: 8, len: usize::MAX }));
+            assert_eq!(alloc_array_excess_in::<u32, _>(&Exhausted, 10),
+                       Err(AllocError::OutOfMemory { layout: Layout::new::<[u32; 10]>() }));
+            assert_eq!(alloc_array_aligned_in::<u8, _>(&Exhausted, 3, 64),
+                       Err(AllocError::OutOfMemory {
+                           layout: Layout::from_size_align(3, 64).unwrap(),
+                       }));
+            assert_eq!(alloc_array_aligned::<u16>(usize::MAX, 64),
+                       Err(AllocError::CapacityOverflow { elem_size: 2, len: usize::MAX }));
+            assert_eq!(alloc_with_tail_in::<u64, u8, _>(&Exhausted, 3).unwrap_err(),
+                       AllocError::OutOfMemory {
+                           layout: Layout::from_size_align(11, 8).unwrap(),
+                       });
+            assert_eq!(alloc_with_tail::<u64, u32>(usize::MAX).unwrap_err(),
+                       AllocError::CapacityOverflow { elem_size: 4, len: usize::MAX });
+
+            let x = alloc_array::<u16>(2).unwrap();
+            assert_eq!(realloc_array_zeroed(x, 2, usize::MAX),
+                       Err(AllocError::CapacityOverflow { elem_size: 2, len: usize::MAX }));
+            assert_eq!(realloc_array_excess(x, 2, usize::MAX),
+                       Err(AllocError::CapacityOverflow { elem_size: 2, len: usize::MAX }));
+            dealloc_array(x, 2);
+
+            // Zero-sized requests never reach the allocator.
+            assert!(alloc_array_aligned_in::<(), _>(&Exhausted, 10, 64).is_ok());
+            assert!(alloc_with_tail_in::<(), (), _>(&Exhausted, 10).is_ok());
+        }
+    }
+}
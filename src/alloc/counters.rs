@@ -0,0 +1,100 @@
+//! The counters behind the `alloc-stats` feature.
+//!
+//! These are updated with relaxed atomics on every allocator call made through `plain`, so they
+//! are cheap, but a snapshot taken while other threads are allocating may be slightly
+//! inconsistent. Without the feature, all of these functions compile to nothing.
+
+#[cfg(feature = "alloc-stats")]
+pub use self::imp::*;
+
+#[cfg(not(feature = "alloc-stats"))]
+pub use self::noop::*;
+
+#[cfg(feature = "alloc-stats")]
+mod imp {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::alloc::stats::AllocStats;
+
+    static BYTES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static BYTES_FREED: AtomicUsize = AtomicUsize::new(0);
+    static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+    static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+    static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+    static GROW_SUCCEEDED: AtomicUsize = AtomicUsize::new(0);
+    static GROW_FAILED: AtomicUsize = AtomicUsize::new(0);
+    static SHRINK_SUCCEEDED: AtomicUsize = AtomicUsize::new(0);
+    static SHRINK_FAILED: AtomicUsize = AtomicUsize::new(0);
+
+    fn add_live(size: usize) {
+        let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+    }
+
+    /// Counts a successful allocation of `size` bytes.
+    pub fn on_alloc(size: usize) {
+        BYTES_ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        add_live(size);
+    }
+
+    /// Counts a deallocation of `size` bytes.
+    pub fn on_dealloc(size: usize) {
+        BYTES_FREED.fetch_add(size, Ordering::Relaxed);
+        LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Counts a call to resize an allocation from `old_size` to `new_size` bytes, which may have
+    /// failed.
+    pub fn on_realloc(old_size: usize, new_size: usize, succeeded: bool) {
+        REALLOCS.fetch_add(1, Ordering::Relaxed);
+        if succeeded {
+            on_dealloc(old_size);
+            on_alloc(new_size);
+        }
+    }
+
+    /// Counts a call to `try_grow_inplace`.
+    pub fn on_grow_inplace(succeeded: bool) {
+        let counter = if succeeded { &GROW_SUCCEEDED } else { &GROW_FAILED };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a call to `try_shrink_inplace`.
+    pub fn on_shrink_inplace(succeeded: bool) {
+        let counter = if succeeded { &SHRINK_SUCCEEDED } else { &SHRINK_FAILED };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reads all of the counters.
+    pub fn snapshot() -> AllocStats {
+        AllocStats {
+            bytes_allocated: BYTES_ALLOCATED.load(Ordering::Relaxed),
+            bytes_freed: BYTES_FREED.load(Ordering::Relaxed),
+            live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+            peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+            reallocs: REALLOCS.load(Ordering::Relaxed),
+            grow_inplace_succeeded: GROW_SUCCEEDED.load(Ordering::Relaxed),
+            grow_inplace_failed: GROW_FAILED.load(Ordering::Relaxed),
+            shrink_inplace_succeeded: SHRINK_SUCCEEDED.load(Ordering::Relaxed),
+            shrink_inplace_failed: SHRINK_FAILED.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(not(feature = "alloc-stats"))]
+#[allow(unused_variables)]
+mod noop {
+    #[inline(always)]
+    pub fn on_alloc(size: usize) {}
+
+    #[inline(always)]
+    pub fn on_dealloc(size: usize) {}
+
+    #[inline(always)]
+    pub fn on_realloc(old_size: usize, new_size: usize, succeeded: bool) {}
+
+    #[inline(always)]
+    pub fn on_grow_inplace(succeeded: bool) {}
+
+    #[inline(always)]
+    pub fn on_shrink_inplace(succeeded: bool) {}
+}
@@ -0,0 +1,193 @@
+//! A `RawAllocator` that catches buffer overflows in hardware, in the style of Electric Fence.
+//!
+//! Every allocation gets its own `mmap`, and is placed so that it ends as close as its alignment
+//! allows to an inaccessible guard page. Reading or writing past the end of the allocation then
+//! segfaults on the spot, instead of silently corrupting a neighbour:
+//!
+//! ```no_run
+//! use raw::alloc::guard::GuardPage;
+//! use raw::alloc::plain;
+//! use raw::rawptr::RawMutPtrExt;
+//!
+//! unsafe {
+//!     let x = plain::alloc_array_in::<u64, _>(&GuardPage, 4);
+//!     RawMutPtrExt::write(x.add(4), 0); // segfaults
+//! }
+//! ```
+//!
+//! This costs at least two pages of address space and a syscall or two per allocation, so it's
+//! only suitable for debugging. Underflows aren't caught, and neither are overflows smaller than
+//! the padding needed to keep the allocation aligned. Freed memory is unmapped, so most accesses
+//! through a dangling ptr also segfault, until the address is mapped again.
+//!
+//! With the `debug-canary` feature, the canaries after an allocation sit between it and the guard
+//! page, so small overflows land on them instead and are only caught when it is freed.
+//!
+//! Only available on Linux.
+
+use std::alloc::Layout;
+use std::ptr;
+use super::allocator::RawAllocator;
+use super::vm::page_size;
+
+/// An allocator that places every allocation directly against an inaccessible guard page.
+///
+/// Use it with the `_in` variants of `plain`, `lazy` and `checked`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GuardPage;
+
+fn round_up(n: usize, to: usize) -> Option<usize> {
+    n.checked_add(to - 1).map(|n| n & !(to - 1))
+}
+
+fn round_down(n: usize, to: usize) -> usize {
+    n & !(to - 1)
+}
+
+unsafe impl RawAllocator for GuardPage {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let page = page_size();
+        // Alignments past a page need slack to slide the allocation down to an aligned address.
+        let slack = if layout.align() > page { layout.align() } else { 0 };
+        let total = round_up(layout.size(), page)
+            .and_then(|n| n.checked_add(slack))
+            .and_then(|n| n.checked_add(page));
+        let total = match total {
+            Some(total) => total,
+            None => return ptr::null_mut(),
+        };
+
+        let base = libc::mmap(ptr::null_mut(), total, libc::PROT_READ | libc::PROT_WRITE,
+                              libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+        if base == libc::MAP_FAILED {
+            return ptr::null_mut();
+        }
+        let base = base as usize;
+
+        // Place the allocation as high as possible, and then trim the mapping to exactly the
+        // pages it touches plus the guard page, so `dealloc` can find them from the ptr alone.
+        let user = round_down(base + total - page - layout.size(), layout.align());
+        let start = round_down(user, page);
+        let guard = round_up(user + layout.size(), page).unwrap();
+        if start > base {
+            libc::munmap(base as *mut _, start - base);
+        }
+        if guard + page < base + total {
+            libc::munmap((guard + page) as *mut _, base + total - guard - page);
+        }
+        if libc::mprotect(guard as *mut _, page, libc::PROT_NONE) != 0 {
+            libc::munmap(start as *mut _, guard + page - start);
+            return ptr::null_mut();
+        }
+        user as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let page = page_size();
+        let start = round_down(ptr as usize, page);
+        let guard = round_up(ptr as usize + layout.size(), page).unwrap();
+        libc::munmap(start as *mut _, guard + page - start);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // Fresh anonymous mappings are always zeroed.
+        self.alloc(layout)
+    }
+
+    fn usable_size(&self, layout: Layout) -> usize {
+        // The rest of the last page is mapped anyway, and the allocation still ends at the guard
+        // page if it fills it.
+        round_up(layout.size(), page_size()).unwrap_or(layout.size())
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::plain;
+    use crate::rawptr::RawPtrExt;
+
+    // Canaries sit between an allocation and its guard page.
+    #[cfg(not(feature = "debug-canary"))]
+    #[test]
+    fn test_alloc_ends_at_guard() {
+        use crate::rawptr::RawMutPtrExt;
+
+        unsafe {
+            let page = page_size();
+            let x = plain::alloc_array_in::<u32, _>(&GuardPage, 10);
+            assert_eq!((x as usize + 40) % page, 0);
+            for i in 0..10 {
+                RawMutPtrExt::write(x.add(i), i as u32);
+            }
+            let x = plain::realloc_array_in(&GuardPage, x, 10, 1000);
+            assert_eq!((x as usize + 4000) % page, 0);
+            for i in 0..10 {
+                assert_eq!(RawPtrExt::read(x.add(i)), i as u32);
+            }
+            plain::dealloc_array_in(&GuardPage, x, 1000);
+
+            // Alignment can leave a gap before the guard page.
+            let x = plain::alloc_array_aligned_in::<u8, _>(&GuardPage, 3, 16);
+            assert_eq!(round_up(x as usize + 3, page).unwrap() - (x as usize + 3), 13);
+            plain::dealloc_array_aligned_in(&GuardPage, x, 3, 16);
+        }
+    }
+
+    #[test]
+    fn test_excess() {
+        unsafe {
+            let page = page_size();
+            let (x, len) = plain::alloc_array_excess_in::<u32, _>(&GuardPage, 10);
+            assert_eq!(len, page / 4);
+            #[cfg(not(feature = "debug-canary"))]
+            assert_eq!(x as usize % page, 0);
+            let (x, len) = plain::realloc_array_excess_in(&GuardPage, x, len, len + 1);
+            assert_eq!(len, page / 2);
+            plain::dealloc_array_in(&GuardPage, x, len);
+        }
+    }
+
+    #[test]
+    fn test_alignment() {
+        unsafe {
+            let page = page_size();
+            let x = plain::alloc_array_aligned_in::<u8, _>(&GuardPage, 3, 16);
+            assert_eq!(x as usize % 16, 0);
+            plain::dealloc_array_aligned_in(&GuardPage, x, 3, 16);
+
+            let x = plain::alloc_array_aligned_in::<u8, _>(&GuardPage, 3, page * 4);
+            assert_eq!(x as usize % (page * 4), 0);
+            *x.add(2) = 1;
+            plain::dealloc_array_aligned_in(&GuardPage, x, 3, page * 4);
+        }
+    }
+
+    #[test]
+    fn test_zeroed() {
+        unsafe {
+            let x = plain::alloc_array_zeroed_in::<u64, _>(&GuardPage, 100);
+            assert!((0..100).all(|i| RawPtrExt::read(x.add(i)) == 0));
+            plain::dealloc_array_in(&GuardPage, x, 100);
+        }
+    }
+
+    // The canaries after the allocation absorb a small overflow.
+    #[cfg(not(feature = "debug-canary"))]
+    #[test]
+    fn test_overflow_segfaults() {
+        use std::os::unix::process::ExitStatusExt;
+        use crate::alloc::test::run_in_child;
+        use crate::rawptr::RawMutPtrExt;
+
+        match run_in_child("alloc::guard::test::test_overflow_segfaults") {
+            Some(status) => assert_eq!(status.signal(), Some(libc::SIGSEGV)),
+            None => unsafe {
+                let x = plain::alloc_array_in::<u64, _>(&GuardPage, 4);
+                RawMutPtrExt::write(x.add(4), 0);
+            },
+        }
+    }
+}
@@ -1,33 +1,82 @@
-use super::{plain, oom};
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use super::{checked, plain, oom};
+use super::allocator::{RawAllocator, Global};
+use super::checked::AllocError;
+
+/// Unwraps the result of a `checked` call, diverting to `oom` or panicking on failure.
+#[inline]
+fn unwrap_or_oom<T>(result: Result<NonNull<T>, AllocError>) -> *mut T {
+    match result {
+        Ok(ptr) => ptr.as_ptr(),
+        Err(AllocError::CapacityOverflow { .. }) => panic!("capacity overflow"),
+        Err(AllocError::OutOfMemory { layout }) => oom(layout),
+    }
+}
+
+/// Diverts to `oom` or panics if an allocation of `len` elements of type T, made through `plain`,
+/// returned `null`.
+#[inline]
+fn check_array<T>(ptr: *mut T, len: usize) -> *mut T {
+    if ptr.is_null() {
+        match Layout::array::<T>(len) {
+            Ok(layout) => oom(layout),
+            Err(_) => panic!("capacity overflow"),
+        }
+    }
+    ptr
+}
 
 /// Allocates and returns a ptr to memory to store a single element of type T. Handles zero-sized
-/// types automatically by returning the non-null EMPTY ptr.
+/// types automatically by returning a non-null dangling ptr.
 ///
-/// # Aborts
+/// # Failure
 ///
-/// Aborts on OOM
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed.
 #[inline]
 pub unsafe fn alloc<T>() -> *mut T {
-    let ptr = plain::alloc::<T>();
-    if ptr.is_null() { oom() }
-    ptr
+    unwrap_or_oom(checked::alloc::<T>())
+}
+
+/// Like `alloc`, but allocates from the given allocator.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed.
+#[inline]
+pub unsafe fn alloc_in<T, A: RawAllocator + ?Sized>(a: &A) -> *mut T {
+    unwrap_or_oom(checked::alloc_in::<T, A>(a))
 }
 
 /// Allocates and returns a ptr to memory to store a `len` elements of type T. Handles zero-sized
-/// types automatically by returning the EMPTY ptr.
+/// types automatically by returning a non-null dangling ptr.
 ///
 /// # Undefined Behaviour
 ///
 /// * `len` must not be 0.
 ///
-/// # Aborts
+/// # Failure
 ///
-/// Aborts on OOM
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
 #[inline]
 pub unsafe fn alloc_array<T>(len: usize) -> *mut T {
-    let ptr = plain::alloc_array::<T>(len);
-    if ptr.is_null() { oom() }
-    ptr
+    unwrap_or_oom(checked::alloc_array::<T>(len))
+}
+
+/// Like `alloc_array`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize) -> *mut T {
+    unwrap_or_oom(checked::alloc_array_in::<T, A>(a, len))
 }
 
 /// Resizes the allocation referenced by `ptr` to fit `len` elements of type T. Handles zero-sized
@@ -38,14 +87,184 @@ pub unsafe fn alloc_array<T>(len: usize) -> *mut T {
 ///
 /// * `len` must not be 0.
 ///
-/// # Aborts
+/// # Failure
 ///
-/// Aborts on OOM
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
 #[inline]
 pub unsafe fn realloc_array<T>(ptr: *mut T, old_len: usize, len: usize) -> *mut T {
-    let ptr = plain::realloc_array(ptr, old_len, len);
-    if ptr.is_null() { oom() }
-    ptr
+    unwrap_or_oom(checked::realloc_array(NonNull::new_unchecked(ptr), old_len, len))
+}
+
+/// Like `realloc_array`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T, old_len: usize,
+                                                             len: usize) -> *mut T {
+    unwrap_or_oom(checked::realloc_array_in(a, NonNull::new_unchecked(ptr), old_len, len))
+}
+
+/// Like `alloc_array`, but also returns the number of elements that actually fit in the
+/// allocation, which is at least `len`. See `plain::alloc_array_excess`.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_array_excess<T>(len: usize) -> (*mut T, usize) {
+    alloc_array_excess_in(&Global, len)
+}
+
+/// Like `alloc_array_excess`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_array_excess_in<T, A: RawAllocator + ?Sized>(a: &A,
+                                                                 len: usize) -> (*mut T, usize) {
+    let (ptr, excess) = plain::alloc_array_excess_in::<T, A>(a, len);
+    (check_array(ptr, len), excess)
+}
+
+/// Like `realloc_array`, but also returns the number of elements that actually fit in the new
+/// allocation, which is at least `len`. See `plain::alloc_array_excess`.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_array_excess<T>(ptr: *mut T, old_len: usize,
+                                      len: usize) -> (*mut T, usize) {
+    realloc_array_excess_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array_excess`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_array_excess_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T,
+                                                                   old_len: usize,
+                                                                   len: usize) -> (*mut T, usize) {
+    let (ptr, excess) = plain::realloc_array_excess_in(a, ptr, old_len, len);
+    (check_array(ptr, len), excess)
+}
+
+/// Like `alloc`, but the returned memory is zeroed.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed.
+#[inline]
+pub unsafe fn alloc_zeroed<T>() -> *mut T {
+    alloc_zeroed_in(&Global)
+}
+
+/// Like `alloc_zeroed`, but allocates from the given allocator.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed.
+#[inline]
+pub unsafe fn alloc_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A) -> *mut T {
+    check_array(plain::alloc_zeroed_in::<T, A>(a), 1)
+}
+
+/// Like `alloc_array`, but the returned memory is zeroed. Uses the allocator's zeroed allocation
+/// path, which is often cheaper than zeroing the memory afterwards.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_array_zeroed<T>(len: usize) -> *mut T {
+    alloc_array_zeroed_in(&Global, len)
+}
+
+/// Like `alloc_array_zeroed`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_array_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize) -> *mut T {
+    check_array(plain::alloc_array_zeroed_in::<T, A>(a, len), len)
+}
+
+/// Like `realloc_array`, but if the allocation grows, the elements past `old_len` are zeroed. The
+/// first `old_len` elements are preserved as usual.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_array_zeroed<T>(ptr: *mut T, old_len: usize, len: usize) -> *mut T {
+    realloc_array_zeroed_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array_zeroed`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_array_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T,
+                                                                    old_len: usize,
+                                                                    len: usize) -> *mut T {
+    check_array(plain::realloc_array_zeroed_in(a, ptr, old_len, len), len)
 }
 
 /// Tries to grow the allocation referenced by `ptr` in-place to fit `len` elements of type `T`.
@@ -55,7 +274,7 @@ pub unsafe fn realloc_array<T>(ptr: *mut T, old_len: usize, len: usize) -> *mut
 /// # Undefined Behaviour
 ///
 /// * `old_len` must be the `len` provided to the last successful allocator call that created or
-/// changed `ptr`.
+///   changed `ptr`.
 /// * `len` must not be 0.
 /// * `len` must not be smaller than `old_len`.
 #[inline]
@@ -70,7 +289,7 @@ pub unsafe fn try_grow_inplace<T>(ptr: *mut T, old_len: usize, len: usize) -> Re
 /// # Undefined Behaviour
 ///
 /// * `old_len` must be the `len` provided to the last successful allocator call that created or
-/// changed `ptr`.
+///   changed `ptr`.
 /// * `len` must not be 0.
 /// * `len` must not be larger than `old_len`.
 #[inline]
@@ -91,6 +310,17 @@ pub unsafe fn dealloc<T>(ptr: *mut T) {
     plain::dealloc(ptr);
 }
 
+/// Like `dealloc`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_in` method with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+#[inline]
+pub unsafe fn dealloc_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T) {
+    plain::dealloc_in(a, ptr);
+}
+
 /// Deallocates the memory referenced by `ptr`, assuming it was allocated with `alloc_array` or
 /// `realloc_array`. Handles zero-sized types automatically by doing nothing.
 ///
@@ -99,8 +329,236 @@ pub unsafe fn dealloc<T>(ptr: *mut T) {
 /// * The `ptr` must have been allocated by this API's `alloc_array` or `realloc_array` methods.
 /// * The `ptr` must not have been previously deallocated.
 /// * `len` must be the `len` provided to the last successful allocator call that created or
-/// changed `ptr`.
+///   changed `ptr`.
 #[inline]
 pub unsafe fn dealloc_array<T>(ptr: *mut T, len: usize) {
     plain::dealloc_array(ptr, len);
 }
+
+/// Like `dealloc_array`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_in` or `realloc_array_in`
+///   methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T, len: usize) {
+    plain::dealloc_array_in(a, ptr, len);
+}
+
+
+/// Allocates and returns a ptr to memory to store a header of type H followed by `len` elements of
+/// type T, with any padding needed to align the elements. Returns the ptr to the header and a raw
+/// slice covering the elements. If both parts are zero-sized, the allocator is not touched and a
+/// non-null dangling ptr is returned.
+///
+/// Unlike `alloc_array`, `len` may be 0.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_with_tail<H, T>(len: usize) -> (*mut H, *mut [T]) {
+    alloc_with_tail_in(&Global, len)
+}
+
+/// Like `alloc_with_tail`, but allocates from the given allocator.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn alloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, len: usize)
+        -> (*mut H, *mut [T]) {
+    let (layout, _) = plain::tail_layout::<H, T>(len).expect("capacity overflow");
+    let result = plain::alloc_with_tail_in::<H, T, A>(a, len);
+    if result.0.is_null() { oom(layout) }
+    result
+}
+
+/// Resizes the allocation referenced by `ptr` to fit a header of type H followed by `len` elements
+/// of type T. The header and the first `min(old_len, len)` elements are preserved. `old_len` must
+/// be the `len` provided to the call to `alloc_with_tail` or `realloc_with_tail` that created
+/// `ptr`.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_with_tail<H, T>(ptr: *mut H, old_len: usize, len: usize)
+        -> (*mut H, *mut [T]) {
+    realloc_with_tail_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_with_tail`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+///
+/// # Failure
+///
+/// Calls `oom` on OOM, which aborts unless another `OomHandler` has been installed. Panics if
+/// the requested size overflows.
+#[inline]
+pub unsafe fn realloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut H,
+                                                                    old_len: usize, len: usize)
+        -> (*mut H, *mut [T]) {
+    let (layout, _) = plain::tail_layout::<H, T>(len).expect("capacity overflow");
+    let result = plain::realloc_with_tail_in::<H, T, A>(a, ptr, old_len, len);
+    if result.0.is_null() { oom(layout) }
+    result
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with `alloc_with_tail` or
+/// `realloc_with_tail`. Handles zero-sized layouts automatically by doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_with_tail` or `realloc_with_tail`
+///   methods.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_with_tail<H, T>(ptr: *mut H, len: usize) {
+    plain::dealloc_with_tail::<H, T>(ptr, len);
+}
+
+/// Like `dealloc_with_tail`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_with_tail_in` or
+///   `realloc_with_tail_in` methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut H,
+                                                                    len: usize) {
+    plain::dealloc_with_tail_in::<H, T, A>(a, ptr, len);
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::OomHandler;
+    use crate::alloc::test::with_oom_handler;
+    use crate::rawslice::{RawSlice, RawMutSlice};
+    use std::panic;
+
+    // Large enough that no allocator will satisfy it, but small enough to be a valid `Layout`.
+    const HUGE: usize = isize::MAX as usize;
+
+    #[test]
+    fn test_alloc_array_oom_panics() {
+        let err = with_oom_handler(OomHandler::Panic, || {
+            panic::catch_unwind(|| unsafe { alloc_array::<u8>(HUGE) }).unwrap_err()
+        });
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert_eq!(*msg, format!("memory allocation of {} bytes failed", HUGE));
+    }
+
+    #[test]
+    fn test_realloc_array_oom_panics() {
+        unsafe {
+            let x = alloc_array::<u8>(4);
+            *x = 9;
+            let result = with_oom_handler(OomHandler::Panic, || {
+                panic::catch_unwind(|| realloc_array(x, 4, HUGE))
+            });
+            assert!(result.is_err());
+            // The original allocation survives.
+            assert_eq!(*x, 9);
+            dealloc_array(x, 4);
+        }
+    }
+
+    #[test]
+    fn test_capacity_overflow_panics() {
+        let result = panic::catch_unwind(|| unsafe { alloc_array::<u64>(usize::MAX) });
+        let err = result.unwrap_err();
+        assert_eq!(*err.downcast_ref::<&str>().unwrap(), "capacity overflow");
+    }
+
+    #[test]
+    fn test_excess() {
+        unsafe {
+            let (x, len) = alloc_array_excess::<u8>(10);
+            assert!(len >= 10);
+            let (x, new_len) = realloc_array_excess(x, len, 20);
+            assert!(new_len >= 20);
+            dealloc_array(x, new_len);
+        }
+        let result = panic::catch_unwind(|| unsafe { alloc_array_excess::<u64>(usize::MAX) });
+        let err = result.unwrap_err();
+        assert_eq!(*err.downcast_ref::<&str>().unwrap(), "capacity overflow");
+    }
+
+    #[test]
+    fn test_with_tail() {
+        unsafe {
+            let (h, tail) = alloc_with_tail::<usize, u8>(5);
+            *h = 5;
+            RawMutSlice::write(tail, 4, 1);
+            let (h, tail) = realloc_with_tail::<usize, u8>(h, 5, 1000);
+            assert_eq!(*h, 5);
+            assert_eq!(RawSlice::read(tail, 4), 1);
+            dealloc_with_tail::<usize, u8>(h, 1000);
+        }
+    }
+
+    #[test]
+    fn test_with_tail_oom_panics() {
+        let result = with_oom_handler(OomHandler::Panic, || {
+            panic::catch_unwind(|| unsafe { alloc_with_tail::<u8, u8>(HUGE - 8) })
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_zeroed() {
+        unsafe {
+            let x = alloc_array_zeroed::<u8>(16);
+            *x = 1;
+            let x = realloc_array_zeroed(x, 16, 32);
+            assert_eq!(*x, 1);
+            for i in 1..32 { assert_eq!(*x.add(i), 0); }
+            dealloc_array(x, 32);
+        }
+    }
+
+    #[test]
+    fn test_zeroed_oom_panics() {
+        let result = with_oom_handler(OomHandler::Panic, || {
+            panic::catch_unwind(|| unsafe { alloc_array_zeroed::<u8>(HUGE) })
+        });
+        assert!(result.is_err());
+        let result = panic::catch_unwind(|| unsafe { alloc_array_zeroed::<u16>(HUGE) });
+        let err = result.unwrap_err();
+        assert_eq!(*err.downcast_ref::<&str>().unwrap(), "capacity overflow");
+    }
+
+    #[test]
+    fn test_alloc() {
+        unsafe {
+            let x = alloc_array::<u32>(3);
+            *x.add(2) = 1;
+            let x = realloc_array(x, 3, 300);
+            assert_eq!(*x.add(2), 1);
+            dealloc_array(x, 300);
+
+            let y = alloc::<u32>();
+            dealloc(y);
+        }
+    }
+}
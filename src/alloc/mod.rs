@@ -1,14 +1,169 @@
 //! Utilities for dealing with the boilerplate of using the allocator directly. In particular,
 //! correctly handles zero-sized types and checks for integer overflows in allocation requests.
 //!
-//! `plain` will return a `null` pointer on OOM, while `lazy` will panic. Otherwise the two
-//! APIs are identical.
+//! `plain` will return a `null` pointer on OOM, while `lazy` will call `oom` and `checked` will
+//! return an `AllocError`. Otherwise the three APIs are identical, except that `lazy` has no
+//! `_aligned` functions.
+//!
+//! The `debug-alloc`, `debug-poison` and `debug-canary` features catch misuse of these APIs, at
+//! some cost in speed and memory.
 
-use std::intrinsics::abort;
+use std::alloc::Layout;
+use std::process::abort;
+use std::sync::RwLock;
 
+pub mod allocator;
+pub mod arena;
+mod canary;
+pub mod checked;
+mod counters;
+#[cfg(target_os = "linux")]
+pub mod guard;
 pub mod lazy;
 pub mod plain;
+pub mod poison;
+pub mod pool;
+#[cfg(any(feature = "debug-alloc", feature = "alloc-stats"))]
+pub mod stats;
+mod tracker;
+#[cfg(target_os = "linux")]
+pub mod vm;
+
+/// What `oom` does when an allocation fails.
+#[derive(Copy, Clone, Debug)]
+pub enum OomHandler {
+    /// Aborts the process. This is the default.
+    Abort,
+    /// Panics with a message describing the failed request. The panic unwinds normally, so it can
+    /// be caught with `std::panic::catch_unwind`.
+    Panic,
+    /// Calls the given function with the layout of the failed request.
+    Hook(fn(Layout) -> !),
+}
+
+static OOM_HANDLER: RwLock<OomHandler> = RwLock::new(OomHandler::Abort);
+
+/// Installs a new process-wide `OomHandler`, returning the previous one.
+pub fn set_oom_handler(handler: OomHandler) -> OomHandler {
+    let mut current = OOM_HANDLER.write().unwrap_or_else(|e| e.into_inner());
+    std::mem::replace(&mut *current, handler)
+}
+
+/// Gets the currently installed `OomHandler`.
+pub fn oom_handler() -> OomHandler {
+    *OOM_HANDLER.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Reports that an allocation of the given layout failed, by invoking the current `OomHandler`.
+pub fn oom(layout: Layout) -> ! {
+    match oom_handler() {
+        OomHandler::Abort => abort(),
+        OomHandler::Panic => panic!("memory allocation of {} bytes failed", layout.size()),
+        OomHandler::Hook(hook) => hook(layout),
+    }
+}
+
+
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::alloc::{GlobalAlloc, System};
+    use std::panic;
+    use std::sync::Mutex;
+    use self::allocator::RawAllocator;
+
+    static HANDLER_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Runs `f`, which must panic, and returns the panic message. Tests of debug checks use it,
+    /// so it can go unused in release builds.
+    #[allow(dead_code)]
+    pub(crate) fn panic_message<F: FnOnce()>(f: F) -> String {
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_err();
+        err.downcast::<String>().map(|s| *s).unwrap_or_default()
+    }
+
+    /// An allocator that never frees, so freed memory can still be inspected, and a freed address
+    /// can't be handed to another test running in parallel. Only feature-gated tests use it.
+    #[allow(dead_code)]
+    pub(crate) struct Leaky;
+
+    unsafe impl RawAllocator for Leaky {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, _: *mut u8, _: Layout) {}
+    }
+
+    /// For tests whose outcome, like an abort or a segfault, can't be observed in-process. In the
+    /// test process, reruns just the test at `path` in a child and returns how the child exited.
+    /// In the child, returns `None`, and the test should then do whatever kills the process.
+    #[cfg(unix)]
+    pub(crate) fn run_in_child(path: &str) -> Option<std::process::ExitStatus> {
+        use std::env;
+        use std::process::{Command, Stdio};
+
+        const CHILD_VAR: &str = "RAW_TEST_CHILD";
+        if env::var_os(CHILD_VAR).is_some() {
+            return None;
+        }
+        let status = Command::new(env::current_exe().unwrap())
+            .args([path, "--exact", "--test-threads=1"])
+            .env(CHILD_VAR, "1")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        Some(status)
+    }
+
+    /// Runs `f` with the given handler installed, serializing against other tests that change it.
+    pub(crate) fn with_oom_handler<R, F: FnOnce() -> R>(handler: OomHandler, f: F) -> R {
+        let _guard = HANDLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let old = set_oom_handler(handler);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+        set_oom_handler(old);
+        result.unwrap_or_else(|e| panic::resume_unwind(e))
+    }
+
+    fn hook(layout: Layout) -> ! {
+        panic::panic_any(layout)
+    }
+
+    #[test]
+    fn test_default_handler() {
+        let _guard = HANDLER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        assert!(matches!(oom_handler(), OomHandler::Abort));
+    }
+
+    #[test]
+    fn test_panic_handler() {
+        let layout = Layout::new::<[u64; 4]>();
+        let err = with_oom_handler(OomHandler::Panic, || {
+            panic::catch_unwind(|| oom(layout)).unwrap_err()
+        });
+        assert_eq!(err.downcast_ref::<String>().unwrap(), "memory allocation of 32 bytes failed");
+    }
+
+    #[test]
+    fn test_hook_handler() {
+        let layout = Layout::new::<[u64; 4]>();
+        let err = with_oom_handler(OomHandler::Hook(hook), || {
+            assert!(matches!(oom_handler(), OomHandler::Hook(_)));
+            panic::catch_unwind(|| oom(layout)).unwrap_err()
+        });
+        assert_eq!(*err.downcast_ref::<Layout>().unwrap(), layout);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_abort_handler() {
+        use std::os::unix::process::ExitStatusExt;
 
-pub fn oom() -> ! {
-    unsafe { abort() }
+        match run_in_child("alloc::test::test_abort_handler") {
+            Some(status) => assert_eq!(status.signal(), Some(6)),
+            None => oom(Layout::new::<u8>()),
+        }
+    }
 }
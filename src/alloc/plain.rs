@@ -1,120 +1,291 @@
-use std::rt::heap::{self, usable_size, EMPTY};
-use std::mem::{size_of, min_align_of};
-use std::usize;
+use std::alloc::Layout;
+use std::any::type_name;
+use std::cmp;
+use std::mem::{size_of, align_of};
+use std::ptr::{self, NonNull};
+use super::allocator::{RawAllocator, Global};
+use super::{canary, counters, poison, tracker};
+use crate::rawptr::{RawPtrExt, RawMutPtrExt};
 
 /// Allocates and returns a ptr to memory to store a single element of type T. Handles zero-sized
-/// types automatically by returning the non-null EMPTY ptr. Returns `null` on OOM.
+/// types automatically by returning a non-null dangling ptr. Returns `null` on OOM.
 #[inline]
 pub unsafe fn alloc<T>() -> *mut T {
+    alloc_in(&Global)
+}
+
+/// Like `alloc`, but allocates from the given allocator.
+#[inline]
+pub unsafe fn alloc_in<T, A: RawAllocator + ?Sized>(a: &A) -> *mut T {
     let size = size_of::<T>();
     if size == 0 {
-        EMPTY as *mut T
+        NonNull::dangling().as_ptr()
     } else {
-        heap::allocate(size, min_align_of::<T>()) as *mut T
+        raw_alloc(a, Layout::new::<T>(), false, type_name::<T>(), size, 1) as *mut T
     }
 }
 
 /// Allocates and returns a ptr to memory to store a `len` elements of type T. Handles zero-sized
-/// types automatically by returning the EMPTY ptr. Returns `null` on OOM.
+/// types automatically by returning a non-null dangling ptr. Returns `null` on OOM, or if the
+/// requested size overflows.
 ///
 /// # Undefined Behaviour
 ///
 /// * `len` must not be 0.
 #[inline]
 pub unsafe fn alloc_array<T>(len: usize) -> *mut T {
+    alloc_array_in(&Global, len)
+}
+
+/// Like `alloc_array`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize) -> *mut T {
     debug_assert!(len != 0, "0 len passed to alloc_array");
     let size = size_of::<T>();
     if size == 0 {
-        EMPTY as *mut T
+        NonNull::dangling().as_ptr()
     } else {
-        let desired_size = size.checked_mul(len).unwrap_or(usize::MAX);
-        heap::allocate(desired_size, min_align_of::<T>()) as *mut T
+        match Layout::array::<T>(len) {
+            Ok(layout) => raw_alloc(a, layout, false, type_name::<T>(), size, len) as *mut T,
+            Err(_) => ptr::null_mut(),
+        }
     }
 }
 
 /// Resizes the allocation referenced by `ptr` to fit `len` elements of type T. Handles zero-sized
 /// types automatically by returning the given ptr. `old_len` must be then `len` provided to the
-/// call to `alloc_array` or `realloc_array` that created `ptr`. Returns `null` on OOM.
+/// call to `alloc_array` or `realloc_array` that created `ptr`. Returns `null` on OOM, or if the
+/// requested size overflows. On failure the original allocation is left untouched.
 ///
 /// # Undefined Behaviour
 ///
 /// * `len` must not be 0.
 #[inline]
 pub unsafe fn realloc_array<T>(ptr: *mut T, old_len: usize, len: usize) -> *mut T {
+    realloc_array_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T, old_len: usize,
+                                                             len: usize) -> *mut T {
     debug_assert!(len != 0, "0 len passed to realloc_array");
     let size = size_of::<T>();
     if size == 0 {
         ptr
     } else {
-        let desired_size = size.checked_mul(len).unwrap_or(usize::MAX);
-        let align = min_align_of::<T>();
-        // No need to check old_size * len, must have been checked when the ptr was made, or
-        // else UB anyway.
-        heap::reallocate(ptr as *mut u8, size * old_len, desired_size, align) as *mut T
+        match Layout::array::<T>(len) {
+            // No need to check size * old_len, must have been checked when the ptr was made, or
+            // else UB anyway.
+            Ok(layout) => raw_realloc(a, "realloc_array", ptr as *mut u8,
+                                      old_layout::<T>(old_len), layout.size(),
+                                      type_name::<T>(), size, old_len, len) as *mut T,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+}
+
+/// Like `alloc_array`, but also returns the number of elements that actually fit in the
+/// allocation, according to `RawAllocator::usable_size`, which is at least `len`. The allocation
+/// is made for exactly that many, so it may be used, resized and freed as an array of that length.
+/// Zero-sized types fit `usize::MAX` elements. Returns `(null, 0)` on OOM, or if the requested
+/// size overflows.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_excess<T>(len: usize) -> (*mut T, usize) {
+    alloc_array_excess_in(&Global, len)
+}
+
+/// Like `alloc_array_excess`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_excess_in<T, A: RawAllocator + ?Sized>(a: &A,
+                                                                 len: usize) -> (*mut T, usize) {
+    match excess_len::<T, A>(a, len) {
+        Some(len) => {
+            let ptr = alloc_array_in(a, len);
+            (ptr, if ptr.is_null() { 0 } else { len })
+        }
+        None => (ptr::null_mut(), 0),
+    }
+}
+
+/// Like `realloc_array`, but also returns the number of elements that actually fit in the new
+/// allocation, as `alloc_array_excess` does. Returns `(null, 0)` on OOM, or if the requested
+/// size overflows, in which case the original allocation is left untouched.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_excess<T>(ptr: *mut T, old_len: usize,
+                                      len: usize) -> (*mut T, usize) {
+    realloc_array_excess_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array_excess`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_excess_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T,
+                                                                   old_len: usize,
+                                                                   len: usize) -> (*mut T, usize) {
+    match excess_len::<T, A>(a, len) {
+        Some(len) => {
+            let ptr = realloc_array_in(a, ptr, old_len, len);
+            (ptr, if ptr.is_null() { 0 } else { len })
+        }
+        None => (ptr::null_mut(), 0),
+    }
+}
+
+/// Like `alloc`, but the returned memory is zeroed.
+#[inline]
+pub unsafe fn alloc_zeroed<T>() -> *mut T {
+    alloc_zeroed_in(&Global)
+}
+
+/// Like `alloc_zeroed`, but allocates from the given allocator.
+#[inline]
+pub unsafe fn alloc_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A) -> *mut T {
+    let size = size_of::<T>();
+    if size == 0 {
+        NonNull::dangling().as_ptr()
+    } else {
+        raw_alloc(a, Layout::new::<T>(), true, type_name::<T>(), size, 1) as *mut T
+    }
+}
+
+/// Like `alloc_array`, but the returned memory is zeroed. Uses the allocator's zeroed allocation
+/// path, which is often cheaper than zeroing the memory afterwards.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_zeroed<T>(len: usize) -> *mut T {
+    alloc_array_zeroed_in(&Global, len)
+}
+
+/// Like `alloc_array_zeroed`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn alloc_array_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize) -> *mut T {
+    debug_assert!(len != 0, "0 len passed to alloc_array_zeroed");
+    let size = size_of::<T>();
+    if size == 0 {
+        NonNull::dangling().as_ptr()
+    } else {
+        match Layout::array::<T>(len) {
+            Ok(layout) => raw_alloc(a, layout, true, type_name::<T>(), size, len) as *mut T,
+            Err(_) => ptr::null_mut(),
+        }
     }
 }
 
+/// Like `realloc_array`, but if the allocation grows, the elements past `old_len` are zeroed. The
+/// first `old_len` elements are preserved as usual.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_zeroed<T>(ptr: *mut T, old_len: usize, len: usize) -> *mut T {
+    realloc_array_zeroed_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_array_zeroed`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_zeroed_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T,
+                                                                    old_len: usize,
+                                                                    len: usize) -> *mut T {
+    let new_ptr = realloc_array_in(a, ptr, old_len, len);
+    if !new_ptr.is_null() && len > old_len {
+        RawMutPtrExt::write_bytes(RawPtrExt::add(new_ptr, old_len), 0, len - old_len);
+    }
+    new_ptr
+}
+
 /// Tries to grow the allocation referenced by `ptr` in-place to fit `len` elements of type `T`.
 /// If successful, yields `Ok`. If unsuccessful, yields `Err`, and the allocation is unchanged.
 /// Handles zero-sized types by always returning `Ok`.
 ///
+/// The global allocator offers no way to resize an allocation without possibly moving it, so
+/// this only succeeds when no resize is needed: that is, when `len` is `old_len`. Any actual
+/// growth yields `Err`, and `realloc_array` should be used instead.
+///
 /// # Undefined Behaviour
 ///
 /// * `old_len` must be the `len` provided to the last successful allocator call that created or
-/// changed `ptr`.
+///   changed `ptr`.
 /// * `len` must not be 0.
 /// * `len` must not be smaller than `old_len`.
 #[inline]
 pub unsafe fn try_grow_inplace<T>(ptr: *mut T, old_len: usize, len: usize) -> Result<(), ()> {
+    debug_assert!(len != 0, "0 len passed to try_grow_inplace");
     debug_assert!(len >= old_len, "new len smaller than old_len in try_grow_inplace");
+    debug_assert!(!ptr.is_null(), "null ptr passed to try_grow_inplace");
     let size = size_of::<T>();
-    let align = min_align_of::<T>();
-    if size == 0 {
-        Ok(())
-    } else {
-        let desired_size = size.checked_mul(len).unwrap_or(usize::MAX);
-        // No need to check size * old_len, must have been checked when the ptr was made, or
-        // else UB anyway.
-        let result_size = heap::reallocate_inplace(ptr as *mut u8, size * old_len,
-                                                    desired_size, align);
-        if result_size >= desired_size {
-            Ok(())
-        } else {
-            Err(())
-        }
+    if size != 0 {
+        tracker::check("try_grow_inplace", ptr as *mut u8, size, old_len, align_of::<T>());
     }
+    let result = if size == 0 || len == old_len { Ok(()) } else { Err(()) };
+    counters::on_grow_inplace(result.is_ok());
+    result
 }
 
 /// Tries to shrink the allocation referenced by `ptr` in-place to fit `len` elements of type `T`.
 /// If successful, yields `Ok`. If unsuccessful, yields `Err`, and the allocation is unchanged.
 /// Handles zero-sized types by always returning `Ok`.
 ///
+/// The global allocator offers no way to resize an allocation without possibly moving it, so
+/// this only succeeds when no resize is needed: that is, when `len` is `old_len`. Any actual
+/// shrinking yields `Err`, and `realloc_array` should be used instead.
+///
 /// # Undefined Behaviour
 ///
 /// * `old_len` must be the `len` provided to the last successful allocator call that created or
-/// changed `ptr`.
+///   changed `ptr`.
 /// * `len` must not be 0.
 /// * `len` must not be larger than `old_len`.
 #[inline]
 pub unsafe fn try_shrink_inplace<T>(ptr: *mut T, old_len: usize, len: usize) -> Result<(), ()> {
     debug_assert!(len != 0, "0 len passed to try_shrink_inplace");
-    debug_assert!(len <= old_len, "new len bigger than old_len in try_grow_inplace");
+    debug_assert!(len <= old_len, "new len bigger than old_len in try_shrink_inplace");
+    debug_assert!(!ptr.is_null(), "null ptr passed to try_shrink_inplace");
     let size = size_of::<T>();
-    let align = min_align_of::<T>();
-    if size == 0 {
-        Ok(())
-    } else {
-        // No need to check either mul, size * len <= size * old_len, and size * old_len must have
-        // been checked when the ptr was made, or else UB anyway.
-        let desired_size = size * len;
-        let result_size = heap::reallocate_inplace(ptr as *mut u8, size * old_len,
-                                                    desired_size, align);
-        if result_size == usable_size(desired_size, align) {
-            Ok(())
-        } else {
-            Err(())
-        }
+    if size != 0 {
+        tracker::check("try_shrink_inplace", ptr as *mut u8, size, old_len, align_of::<T>());
     }
+    let result = if size == 0 || len == old_len { Ok(()) } else { Err(()) };
+    counters::on_shrink_inplace(result.is_ok());
+    result
 }
 
 
@@ -127,11 +298,22 @@ pub unsafe fn try_shrink_inplace<T>(ptr: *mut T, old_len: usize, len: usize) ->
 /// * The `ptr` must not have been previously deallocated.
 #[inline]
 pub unsafe fn dealloc<T>(ptr: *mut T) {
+    dealloc_in(&Global, ptr)
+}
+
+/// Like `dealloc`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_in` method with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+#[inline]
+pub unsafe fn dealloc_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T) {
     let size = size_of::<T>();
     if size == 0 {
         // Do nothing
     } else {
-        heap::deallocate(ptr as *mut u8, size, min_align_of::<T>());
+        raw_dealloc(a, "dealloc", ptr as *mut u8, Layout::new::<T>(), size, 1);
     }
 }
 
@@ -143,15 +325,689 @@ pub unsafe fn dealloc<T>(ptr: *mut T) {
 /// * The `ptr` must have been allocated by this API's `alloc_array` or `realloc_array` methods.
 /// * The `ptr` must not have been previously deallocated.
 /// * `len` must be the `len` provided to the last successful allocator call that created or
-/// changed `ptr`.
+///   changed `ptr`.
 #[inline]
 pub unsafe fn dealloc_array<T>(ptr: *mut T, len: usize) {
+    dealloc_array_in(&Global, ptr, len)
+}
+
+/// Like `dealloc_array`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_in` or `realloc_array_in`
+///   methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T, len: usize) {
+    let size = size_of::<T>();
+    if size == 0 {
+        // Do nothing
+    } else {
+        raw_dealloc(a, "dealloc_array", ptr as *mut u8, old_layout::<T>(len), size, len);
+    }
+}
+
+/// Allocates and returns a ptr to memory to store `len` elements of type T, aligned to at least
+/// `align` bytes. Handles zero-sized types automatically by returning a non-null dangling ptr with
+/// that alignment. Returns `null` on OOM, or if the requested size overflows.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+/// * `align` must be a power of two.
+#[inline]
+pub unsafe fn alloc_array_aligned<T>(len: usize, align: usize) -> *mut T {
+    alloc_array_aligned_in(&Global, len, align)
+}
+
+/// Like `alloc_array_aligned`, but allocates from the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+/// * `align` must be a power of two.
+#[inline]
+pub unsafe fn alloc_array_aligned_in<T, A: RawAllocator + ?Sized>(a: &A, len: usize,
+                                                                   align: usize) -> *mut T {
+    debug_assert!(len != 0, "0 len passed to alloc_array_aligned");
+    debug_assert!(align.is_power_of_two(), "non-power-of-two align passed to alloc_array_aligned");
+    let size = size_of::<T>();
+    if size == 0 {
+        dangling(cmp::max(align, align_of::<T>())) as *mut T
+    } else {
+        match aligned_layout::<T>(len, align) {
+            Some(layout) => raw_alloc(a, layout, false, type_name::<T>(), size, len) as *mut T,
+            None => ptr::null_mut(),
+        }
+    }
+}
+
+/// Resizes the allocation referenced by `ptr` to fit `len` elements of type T, keeping its
+/// alignment. Handles zero-sized types automatically by returning the given ptr. `old_len` and
+/// `align` must be the ones provided to the call to `alloc_array_aligned` or
+/// `realloc_array_aligned` that created `ptr`. Returns `null` on OOM, or if the requested size
+/// overflows. On failure the original allocation is left untouched.
+///
+/// # Undefined Behaviour
+///
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_aligned<T>(ptr: *mut T, old_len: usize, len: usize,
+                                       align: usize) -> *mut T {
+    realloc_array_aligned_in(&Global, ptr, old_len, len, align)
+}
+
+/// Like `realloc_array_aligned`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+/// * `len` must not be 0.
+#[inline]
+pub unsafe fn realloc_array_aligned_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T,
+                                                                     old_len: usize, len: usize,
+                                                                     align: usize) -> *mut T {
+    debug_assert!(len != 0, "0 len passed to realloc_array_aligned");
+    debug_assert!((ptr as usize).is_multiple_of(align),
+                  "misaligned ptr passed to realloc_array_aligned");
+    let size = size_of::<T>();
+    if size == 0 {
+        ptr
+    } else {
+        match aligned_layout::<T>(len, align) {
+            // No need to check the old layout, must have been checked when the ptr was made, or
+            // else UB anyway.
+            Some(layout) => {
+                let old = Layout::from_size_align_unchecked(size * old_len, layout.align());
+                raw_realloc(a, "realloc_array_aligned", ptr as *mut u8, old, layout.size(),
+                            type_name::<T>(), size, old_len, len) as *mut T
+            }
+            None => ptr::null_mut(),
+        }
+    }
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with
+/// `alloc_array_aligned` or `realloc_array_aligned`. Handles zero-sized types automatically by
+/// doing nothing. In debug builds, panics if `ptr` is not aligned to `align`.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_aligned` or
+///   `realloc_array_aligned` methods.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` and `align` must be the ones provided to the last successful allocator call that
+///   created or changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_aligned<T>(ptr: *mut T, len: usize, align: usize) {
+    dealloc_array_aligned_in(&Global, ptr, len, align)
+}
+
+/// Like `dealloc_array_aligned`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_array_aligned_in` or
+///   `realloc_array_aligned_in` methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` and `align` must be the ones provided to the last successful allocator call that
+///   created or changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_array_aligned_in<T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut T,
+                                                                     len: usize, align: usize) {
+    debug_assert!((ptr as usize).is_multiple_of(align),
+                  "misaligned ptr passed to dealloc_array_aligned");
     let size = size_of::<T>();
     if size == 0 {
         // Do nothing
     } else {
         // No need to check size * len, must have been checked when the ptr was made, or
         // else UB anyway.
-        heap::deallocate(ptr as *mut u8, size * len, min_align_of::<T>());
+        let align = cmp::max(align, align_of::<T>());
+        let layout = Layout::from_size_align_unchecked(size * len, align);
+        raw_dealloc(a, "dealloc_array_aligned", ptr as *mut u8, layout, size, len);
+    }
+}
+
+/// Allocates and returns a ptr to memory to store a header of type H followed by `len` elements of
+/// type T, with any padding needed to align the elements. Returns the ptr to the header and a raw
+/// slice covering the elements. If both parts are zero-sized, the allocator is not touched and a
+/// non-null dangling ptr is returned. Returns `null` ptrs on OOM, or if the requested size
+/// overflows.
+///
+/// Unlike `alloc_array`, `len` may be 0.
+#[inline]
+pub unsafe fn alloc_with_tail<H, T>(len: usize) -> (*mut H, *mut [T]) {
+    alloc_with_tail_in(&Global, len)
+}
+
+/// Like `alloc_with_tail`, but allocates from the given allocator.
+#[inline]
+pub unsafe fn alloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, len: usize)
+        -> (*mut H, *mut [T]) {
+    match tail_layout::<H, T>(len) {
+        Some((layout, offset)) => {
+            let ptr = if layout.size() == 0 {
+                dangling(layout.align())
+            } else {
+                raw_alloc(a, layout, false, type_name::<H>(), size_of::<T>(), len)
+            };
+            split_tail(ptr, offset, len)
+        }
+        None => split_tail(ptr::null_mut(), 0, len),
+    }
+}
+
+/// Resizes the allocation referenced by `ptr` to fit a header of type H followed by `len` elements
+/// of type T. The header and the first `min(old_len, len)` elements are preserved. `old_len` must
+/// be the `len` provided to the call to `alloc_with_tail` or `realloc_with_tail` that created
+/// `ptr`. Returns `null` ptrs on OOM, or if the requested size overflows. On failure the original
+/// allocation is left untouched.
+#[inline]
+pub unsafe fn realloc_with_tail<H, T>(ptr: *mut H, old_len: usize, len: usize)
+        -> (*mut H, *mut [T]) {
+    realloc_with_tail_in(&Global, ptr, old_len, len)
+}
+
+/// Like `realloc_with_tail`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been allocated by `a`.
+#[inline]
+pub unsafe fn realloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut H,
+                                                                    old_len: usize, len: usize)
+        -> (*mut H, *mut [T]) {
+    // No need to check the old layout, must have been checked when the ptr was made, or
+    // else UB anyway.
+    let (old, _) = tail_layout::<H, T>(old_len).unwrap();
+    match tail_layout::<H, T>(len) {
+        Some((layout, offset)) => {
+            let new_ptr = if layout.size() == 0 {
+                if old.size() != 0 {
+                    raw_dealloc(a, "realloc_with_tail", ptr as *mut u8, old, size_of::<T>(),
+                                old_len);
+                }
+                dangling(layout.align())
+            } else if old.size() == 0 {
+                raw_alloc(a, layout, false, type_name::<H>(), size_of::<T>(), len)
+            } else {
+                raw_realloc(a, "realloc_with_tail", ptr as *mut u8, old, layout.size(),
+                            type_name::<H>(), size_of::<T>(), old_len, len)
+            };
+            split_tail(new_ptr, offset, len)
+        }
+        None => split_tail(ptr::null_mut(), 0, len),
+    }
+}
+
+/// Deallocates the memory referenced by `ptr`, assuming it was allocated with `alloc_with_tail` or
+/// `realloc_with_tail`. Handles zero-sized layouts automatically by doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_with_tail` or `realloc_with_tail`
+///   methods.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_with_tail<H, T>(ptr: *mut H, len: usize) {
+    dealloc_with_tail_in::<H, T, _>(&Global, ptr, len)
+}
+
+/// Like `dealloc_with_tail`, but for an allocation made by the given allocator.
+///
+/// # Undefined Behaviour
+///
+/// * The `ptr` must have been allocated by this API's `alloc_with_tail_in` or
+///   `realloc_with_tail_in` methods with the same allocator.
+/// * The `ptr` must not have been previously deallocated.
+/// * `len` must be the `len` provided to the last successful allocator call that created or
+///   changed `ptr`.
+#[inline]
+pub unsafe fn dealloc_with_tail_in<H, T, A: RawAllocator + ?Sized>(a: &A, ptr: *mut H,
+                                                                    len: usize) {
+    let (layout, _) = tail_layout::<H, T>(len).unwrap();
+    if layout.size() != 0 {
+        raw_dealloc(a, "dealloc_with_tail", ptr as *mut u8, layout, size_of::<T>(), len);
+    }
+}
+
+/// Every allocator call in this module goes through `raw_alloc`, `raw_realloc` or `raw_dealloc`,
+/// so that the debugging features see every allocation. `type_name`, `elem_size` and `len`
+/// describe the allocation in the caller's terms; `op` names the public function for error
+/// messages.
+#[inline]
+unsafe fn raw_alloc<A: RawAllocator + ?Sized>(a: &A, layout: Layout, zeroed: bool,
+                                              type_name: &'static str, elem_size: usize,
+                                              len: usize) -> *mut u8 {
+    let padded = match canary::padded_layout(layout) {
+        Some(padded) => padded,
+        None => return ptr::null_mut(),
+    };
+    let ptr = if zeroed {
+        canary::on_alloc(a.alloc_zeroed(padded), layout)
+    } else {
+        let ptr = canary::on_alloc(a.alloc(padded), layout);
+        poison::on_alloc(ptr, layout.size());
+        ptr
+    };
+    if !ptr.is_null() {
+        counters::on_alloc(layout.size());
+    }
+    tracker::on_alloc(ptr, type_name, elem_size, len, layout.align());
+    ptr
+}
+
+#[allow(clippy::too_many_arguments)]
+#[inline]
+unsafe fn raw_realloc<A: RawAllocator + ?Sized>(a: &A, op: &str, ptr: *mut u8, old: Layout,
+                                                new_size: usize, type_name: &'static str,
+                                                elem_size: usize, old_len: usize,
+                                                len: usize) -> *mut u8 {
+    // Canaries are only read once the ptr is known to be live, and a corrupted one must leave it
+    // live in the table.
+    tracker::check(op, ptr, elem_size, old_len, old.align());
+    let raw = canary::verify(op, ptr, old, old_len);
+    let released = tracker::release(op, ptr, elem_size, old_len, old.align());
+    let tail = RawPtrExt::add(ptr, cmp::min(new_size, old.size()));
+    let shrunk = poison::on_shrink(tail, old.size().saturating_sub(new_size));
+    let new = Layout::from_size_align_unchecked(new_size, old.align());
+    let new_raw = match canary::padded_layout(new) {
+        Some(padded) => a.realloc(raw, canary::padded_layout(old).unwrap(), padded.size()),
+        None => ptr::null_mut(),
+    };
+    let new_ptr = canary::on_alloc(new_raw, new);
+    counters::on_realloc(old.size(), new_size, !new_ptr.is_null());
+    if new_ptr.is_null() {
+        poison::restore(tail, shrunk);
+        tracker::restore(ptr, released);
+    } else {
+        if new_size > old.size() {
+            poison::on_alloc(RawPtrExt::add(new_ptr, old.size()), new_size - old.size());
+        }
+        tracker::on_alloc(new_ptr, type_name, elem_size, len, old.align());
+    }
+    new_ptr
+}
+
+#[inline]
+unsafe fn raw_dealloc<A: RawAllocator + ?Sized>(a: &A, op: &str, ptr: *mut u8, layout: Layout,
+                                                elem_size: usize, len: usize) {
+    tracker::check(op, ptr, elem_size, len, layout.align());
+    let raw = canary::verify(op, ptr, layout, len);
+    tracker::release(op, ptr, elem_size, len, layout.align());
+    poison::on_dealloc(ptr, layout.size());
+    a.dealloc(raw, canary::padded_layout(layout).unwrap());
+    counters::on_dealloc(layout.size());
+}
+
+/// Computes how many elements of type T fit in an allocation of `len` of them from `a`. Returns
+/// `None` on overflow.
+#[inline]
+fn excess_len<T, A: RawAllocator + ?Sized>(a: &A, len: usize) -> Option<usize> {
+    let size = size_of::<T>();
+    if size == 0 {
+        return Some(usize::MAX);
+    }
+    let layout = Layout::array::<T>(len).ok()?;
+    Some(cmp::max(len, a.usable_size(layout) / size))
+}
+
+/// Computes the layout of a header of type H followed by `len` elements of type T, and the offset
+/// of the elements. Returns `None` on overflow.
+#[inline]
+pub(super) fn tail_layout<H, T>(len: usize) -> Option<(Layout, usize)> {
+    Layout::new::<H>().extend(Layout::array::<T>(len).ok()?).ok()
+}
+
+/// Computes the layout of `len` elements of type T, aligned to at least `align`. Returns `None` on
+/// overflow.
+#[inline]
+pub(super) fn aligned_layout<T>(len: usize, align: usize) -> Option<Layout> {
+    Layout::array::<T>(len).ok()?.align_to(align).ok()
+}
+
+/// Splits a header-with-tail allocation into its two parts. A `null` ptr yields `null` parts.
+#[inline]
+unsafe fn split_tail<H, T>(ptr: *mut u8, offset: usize, len: usize) -> (*mut H, *mut [T]) {
+    let tail = if ptr.is_null() {
+        ptr::null_mut()
+    } else {
+        RawPtrExt::add(ptr, offset) as *mut T
+    };
+    (ptr as *mut H, ptr::slice_from_raw_parts_mut(tail, len))
+}
+
+/// Makes a non-null dangling ptr with the given alignment.
+#[inline]
+fn dangling(align: usize) -> *mut u8 {
+    ptr::null_mut::<u8>().wrapping_add(align)
+}
+
+/// Rebuilds the layout of an existing allocation of `len` elements of type `T`.
+#[inline]
+unsafe fn old_layout<T>(len: usize) -> Layout {
+    // No need to check size * len, must have been checked when the ptr was made, or
+    // else UB anyway.
+    Layout::from_size_align_unchecked(size_of::<T>() * len, align_of::<T>())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::allocator::GlobalAdapter;
+    use std::alloc::{GlobalAlloc, System};
+    use std::cell::Cell;
+    use crate::rawslice::{RawSlice, RawMutSlice};
+
+    #[test]
+    fn test_alloc_dealloc() {
+        unsafe {
+            let x = alloc::<u64>();
+            assert!(!x.is_null());
+            *x = 5;
+            assert_eq!(*x, 5);
+            dealloc(x);
+
+            let z = alloc::<()>();
+            assert!(!z.is_null());
+            dealloc(z);
+        }
+    }
+
+    #[test]
+    fn test_alloc_array() {
+        unsafe {
+            let x = alloc_array::<u32>(4);
+            assert!(!x.is_null());
+            for i in 0..4 { *RawPtrExt::add(x, i as usize) = i; }
+            let x = realloc_array(x, 4, 100);
+            assert!(!x.is_null());
+            for i in 0..4 { assert_eq!(*RawPtrExt::add(x, i as usize), i); }
+            let x = realloc_array(x, 100, 2);
+            assert!(!x.is_null());
+            assert_eq!(*RawPtrExt::add(x, 1), 1);
+            dealloc_array(x, 2);
+        }
+    }
+
+    #[test]
+    fn test_zst() {
+        unsafe {
+            let x = alloc_array::<()>(usize::MAX);
+            assert!(!x.is_null());
+            assert_eq!(realloc_array(x, usize::MAX, 10), x);
+            assert_eq!(try_grow_inplace(x, 10, 20), Ok(()));
+            assert_eq!(try_shrink_inplace(x, 20, 1), Ok(()));
+            dealloc_array(x, 1);
+        }
+    }
+
+    struct Counting {
+        live: Cell<isize>,
+    }
+
+    unsafe impl RawAllocator for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.live.set(self.live.get() + 1);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    /// An allocator that claims its memory comes in multiples of 64 bytes.
+    struct SizeClasses;
+
+    unsafe impl RawAllocator for SizeClasses {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        fn usable_size(&self, layout: Layout) -> usize {
+            layout.size().div_ceil(64) * 64
+        }
+    }
+
+    #[test]
+    fn test_excess() {
+        unsafe {
+            let (x, len) = alloc_array_excess_in::<u32, _>(&SizeClasses, 5);
+            assert_eq!(len, 16);
+            for i in 0..16 { *RawPtrExt::add(x, i) = i as u32; }
+            let (x, len) = realloc_array_excess_in(&SizeClasses, x, 16, 20);
+            assert_eq!(len, 32);
+            for i in 0..16 { assert_eq!(*RawPtrExt::add(x, i), i as u32); }
+            dealloc_array_in(&SizeClasses, x, 32);
+
+            // `Global` has no slack to report.
+            let (x, len) = alloc_array_excess::<u16>(3);
+            assert_eq!(len, 3);
+            let (x, len) = realloc_array_excess(x, 3, 7);
+            assert_eq!(len, 7);
+            dealloc_array(x, 7);
+
+            let (x, len) = alloc_array_excess::<()>(3);
+            assert_eq!((x, len), (NonNull::dangling().as_ptr(), usize::MAX));
+            assert_eq!(alloc_array_excess::<u64>(usize::MAX), (ptr::null_mut(), 0));
+        }
+    }
+
+    #[test]
+    fn test_alloc_in() {
+        unsafe {
+            let a = Counting { live: Cell::new(0) };
+            let x = alloc_in::<u8, _>(&a);
+            let y = alloc_array_in::<u32, _>(&a, 4);
+            let z = alloc_array_in::<(), _>(&a, 4);
+            assert_eq!(a.live.get(), 2);
+            for i in 0..4 { *RawPtrExt::add(y, i) = i as u32; }
+
+            // Goes through the default copying realloc.
+            let y = realloc_array_in(&a, y, 4, 64);
+            assert_eq!(a.live.get(), 2);
+            for i in 0..4 { assert_eq!(*RawPtrExt::add(y, i), i as u32); }
+
+            dealloc_in(&a, x);
+            dealloc_array_in(&a, y, 64);
+            dealloc_array_in(&a, z, 4);
+            assert_eq!(a.live.get(), 0);
+
+            assert!(alloc_array_in::<u64, _>(&a, usize::MAX).is_null());
+            assert_eq!(a.live.get(), 0);
+        }
+    }
+
+    #[test]
+    fn test_global_adapter() {
+        unsafe {
+            let a = GlobalAdapter(System);
+            let x = alloc_array_in::<u16, _>(&a, 3);
+            assert!(!x.is_null());
+            let x = realloc_array_in(&a, x, 3, 6);
+            assert!(!x.is_null());
+            dealloc_array_in(&a, x, 6);
+        }
+    }
+
+    #[test]
+    fn test_zeroed() {
+        unsafe {
+            let x = alloc_zeroed::<u64>();
+            assert_eq!(*x, 0);
+            dealloc(x);
+
+            let x = alloc_array_zeroed::<u32>(100);
+            for i in 0..100 { assert_eq!(*RawPtrExt::add(x, i), 0); }
+            *x = 1;
+            let x = realloc_array_zeroed(x, 100, 1000);
+            assert_eq!(*x, 1);
+            for i in 1..1000 { assert_eq!(*RawPtrExt::add(x, i), 0); }
+            let x = realloc_array_zeroed(x, 1000, 10);
+            assert_eq!(*x, 1);
+            dealloc_array(x, 10);
+
+            assert!(alloc_array_zeroed::<u16>(usize::MAX).is_null());
+            assert!(!alloc_array_zeroed::<()>(usize::MAX).is_null());
+        }
+    }
+
+    #[test]
+    fn test_zeroed_default_impl() {
+        unsafe {
+            // `Counting` relies on the default `alloc_zeroed`, so make sure it really zeroes.
+            let a = Counting { live: Cell::new(0) };
+            let x = alloc_array_in::<u8, _>(&a, 64);
+            RawMutPtrExt::write_bytes(x, 0xAB, 64);
+            dealloc_array_in(&a, x, 64);
+            let x = alloc_array_zeroed_in::<u8, _>(&a, 64);
+            for i in 0..64 { assert_eq!(*RawPtrExt::add(x, i), 0); }
+            dealloc_array_in(&a, x, 64);
+            assert_eq!(a.live.get(), 0);
+        }
+    }
+
+    #[test]
+    fn test_aligned() {
+        unsafe {
+            for &align in &[1, 8, 64, 4096] {
+                let x = alloc_array_aligned::<u16>(3, align);
+                assert!(!x.is_null());
+                assert_eq!(x as usize % align, 0);
+                *RawPtrExt::add(x, 2) = 4;
+                let x = realloc_array_aligned(x, 3, 5000, align);
+                assert_eq!(x as usize % align, 0);
+                assert_eq!(*RawPtrExt::add(x, 2), 4);
+                dealloc_array_aligned(x, 5000, align);
+            }
+
+            // Never less aligned than T itself.
+            let x = alloc_array_aligned::<u64>(1, 1);
+            assert_eq!(x as usize % 8, 0);
+            dealloc_array_aligned(x, 1, 1);
+        }
+    }
+
+    #[test]
+    fn test_aligned_zst_overflow() {
+        unsafe {
+            let x = alloc_array_aligned::<()>(usize::MAX, 4096);
+            assert!(!x.is_null());
+            assert_eq!(x as usize % 4096, 0);
+            assert_eq!(realloc_array_aligned(x, usize::MAX, 1, 4096), x);
+            dealloc_array_aligned(x, 1, 4096);
+
+            assert!(alloc_array_aligned::<u32>(usize::MAX / 4, 64).is_null());
+            assert!(alloc_array_aligned::<u8>(isize::MAX as usize, 64).is_null());
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "misaligned ptr passed to dealloc_array_aligned")]
+    fn test_aligned_dealloc_checks_align() {
+        unsafe {
+            let x = alloc_array_aligned::<u8>(64, 64);
+            dealloc_array_aligned(RawPtrExt::add(x, 1), 63, 64);
+        }
+    }
+
+    #[test]
+    fn test_with_tail() {
+        unsafe {
+            let (h, tail) = alloc_with_tail::<u8, u64>(3);
+            assert!(!h.is_null());
+            assert_eq!(tail.len(), 3);
+            assert_eq!(tail as *mut u64 as usize - h as usize, 8);
+            *h = 1;
+            for i in 0..3 { RawMutSlice::write(tail, i, i as u64); }
+
+            let (h, tail) = realloc_with_tail::<u8, u64>(h, 3, 10);
+            assert_eq!(*h, 1);
+            assert_eq!(tail.len(), 10);
+            for i in 0..3 { assert_eq!(RawSlice::read(tail, i), i as u64); }
+            dealloc_with_tail::<u8, u64>(h, 10);
+
+            let (h, tail) = alloc_with_tail::<u32, u8>(0);
+            assert!(!h.is_null());
+            assert_eq!(tail.len(), 0);
+            dealloc_with_tail::<u32, u8>(h, 0);
+        }
+    }
+
+    #[test]
+    fn test_with_tail_zst() {
+        unsafe {
+            #[repr(align(16))]
+            struct Aligned;
+            let (h, tail) = alloc_with_tail::<Aligned, ()>(usize::MAX);
+            assert!(!h.is_null());
+            assert_eq!(h as usize % 16, 0);
+            assert_eq!(tail.len(), usize::MAX);
+            let (h2, _) = realloc_with_tail::<Aligned, ()>(h, usize::MAX, 1);
+            assert_eq!(h, h2);
+            dealloc_with_tail::<Aligned, ()>(h2, 1);
+
+            // A zero-sized header still allocates for a non-empty tail, and frees it again
+            // when the tail empties.
+            let (h, _) = alloc_with_tail::<(), u16>(0);
+            let (h, tail) = realloc_with_tail::<(), u16>(h, 0, 4);
+            assert!(!h.is_null());
+            RawMutSlice::write(tail, 3, 5);
+            let (h, _) = realloc_with_tail::<(), u16>(h, 4, 0);
+            assert!(!h.is_null());
+            dealloc_with_tail::<(), u16>(h, 0);
+        }
+    }
+
+    #[test]
+    fn test_with_tail_overflow() {
+        unsafe {
+            let (h, _) = alloc_with_tail::<u8, u32>(usize::MAX / 4);
+            assert!(h.is_null());
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        unsafe {
+            assert!(alloc_array::<u64>(usize::MAX).is_null());
+            assert!(alloc_array::<u8>(usize::MAX).is_null());
+
+            let x = alloc_array::<u64>(4);
+            assert!(!x.is_null());
+            *x = 7;
+            assert!(realloc_array(x, 4, usize::MAX / 2).is_null());
+            // The old allocation must survive a failed realloc.
+            assert_eq!(*x, 7);
+            dealloc_array(x, 4);
+        }
+    }
+
+    #[test]
+    fn test_inplace() {
+        unsafe {
+            let x = alloc_array::<u16>(8);
+            assert_eq!(try_grow_inplace(x, 8, 8), Ok(()));
+            assert_eq!(try_grow_inplace(x, 8, 16), Err(()));
+            assert_eq!(try_shrink_inplace(x, 8, 8), Ok(()));
+            assert_eq!(try_shrink_inplace(x, 8, 4), Err(()));
+            // Failure leaves the allocation at its old len.
+            dealloc_array(x, 8);
+        }
     }
 }
@@ -0,0 +1,185 @@
+//! The byte patterns written by the `debug-poison` feature.
+//!
+//! With the feature enabled, memory handed out by `plain` (and so by `lazy` and `checked`) is
+//! filled with `ALLOC_POISON`, except by the zeroed entry points, and this includes the new tail
+//! of an allocation grown by a realloc. Memory is filled with `FREE_POISON` just before it is
+//! given back, and so is the tail a realloc is about to cut off, which is restored if the realloc
+//! fails. Reads of uninitialized memory then produce recognizable values, and so do reads
+//! through dangling pointers, for as long as the allocator leaves the freed memory alone.
+//!
+//! `RawSlice::is_poisoned` checks for `ALLOC_POISON`.
+
+/// The byte newly allocated memory is filled with.
+pub const ALLOC_POISON: u8 = 0xCD;
+
+/// The byte memory is filled with just before it is freed.
+pub const FREE_POISON: u8 = 0xDD;
+
+#[cfg(feature = "debug-poison")]
+pub(crate) use self::imp::*;
+
+#[cfg(not(feature = "debug-poison"))]
+pub(crate) use self::noop::*;
+
+#[cfg(feature = "debug-poison")]
+mod imp {
+    use std::ptr;
+    use std::slice;
+    use super::{ALLOC_POISON, FREE_POISON};
+
+    /// The original contents of a tail poisoned by `on_shrink`, in case they need to be restored.
+    pub struct Shrunk(Vec<u8>);
+
+    /// Poisons `len` bytes of newly allocated memory at `ptr`. `null` ptrs are ignored.
+    pub unsafe fn on_alloc(ptr: *mut u8, len: usize) {
+        if !ptr.is_null() {
+            ptr::write_bytes(ptr, ALLOC_POISON, len);
+        }
+    }
+
+    /// Poisons `len` bytes of memory at `ptr` that is about to be freed.
+    pub unsafe fn on_dealloc(ptr: *mut u8, len: usize) {
+        ptr::write_bytes(ptr, FREE_POISON, len);
+    }
+
+    /// Like `on_dealloc`, for the `len` bytes at `ptr` that a shrinking realloc is about to cut
+    /// off. Once the realloc succeeds the memory is no longer ours to poison, so this must happen
+    /// before it. If the realloc then fails, the caller must `restore` the tail.
+    pub unsafe fn on_shrink(ptr: *mut u8, len: usize) -> Shrunk {
+        let saved = slice::from_raw_parts(ptr, len).to_vec();
+        on_dealloc(ptr, len);
+        Shrunk(saved)
+    }
+
+    /// Undoes an `on_shrink` of the tail at `ptr` whose realloc failed.
+    pub unsafe fn restore(ptr: *mut u8, shrunk: Shrunk) {
+        ptr::copy_nonoverlapping(shrunk.0.as_ptr(), ptr, shrunk.0.len());
+    }
+}
+
+#[cfg(not(feature = "debug-poison"))]
+#[allow(unused_variables)]
+mod noop {
+    pub struct Shrunk;
+
+    #[inline(always)]
+    pub unsafe fn on_alloc(ptr: *mut u8, len: usize) {}
+
+    #[inline(always)]
+    pub unsafe fn on_dealloc(ptr: *mut u8, len: usize) {}
+
+    #[inline(always)]
+    pub unsafe fn on_shrink(ptr: *mut u8, len: usize) -> Shrunk {
+        Shrunk
+    }
+
+    #[inline(always)]
+    pub unsafe fn restore(ptr: *mut u8, shrunk: Shrunk) {}
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+    use crate::rawslice::RawSlice;
+
+    #[test]
+    fn test_is_poisoned() {
+        unsafe {
+            let mut buf = [ALLOC_POISON; 8];
+            let bytes = buf.as_mut_ptr();
+            let s = ptr::slice_from_raw_parts(bytes as *const u32, 2);
+            assert!(s.is_poisoned());
+            bytes.add(5).write(0);
+            assert!(!s.is_poisoned());
+            assert!(s.slice(0, 1).is_poisoned());
+            assert!(s.slice(2, 2).is_poisoned());
+        }
+    }
+
+    #[cfg(feature = "debug-poison")]
+    mod feature {
+        use super::super::*;
+        use std::alloc::Layout;
+        use std::ptr;
+        use crate::alloc::allocator::{RawAllocator, Global};
+        use crate::alloc::plain::*;
+        use crate::alloc::test::Leaky;
+        use crate::rawslice::{RawSlice, RawMutSlice};
+
+        #[test]
+        fn test_alloc_poisoned() {
+            unsafe {
+                let x = alloc_array::<u16>(5);
+                assert!(ptr::slice_from_raw_parts(x, 5).is_poisoned());
+                dealloc_array(x, 5);
+
+                let x = alloc::<u64>();
+                assert!(ptr::slice_from_raw_parts(x, 1).is_poisoned());
+                dealloc(x);
+
+                let x = alloc_array_zeroed::<u16>(5);
+                assert!(!ptr::slice_from_raw_parts(x, 5).is_poisoned());
+                dealloc_array(x, 5);
+            }
+        }
+
+        #[test]
+        fn test_realloc_poisons_tail() {
+            unsafe {
+                let x = alloc_array::<u32>(2);
+                let s = ptr::slice_from_raw_parts_mut(x, 2);
+                RawMutSlice::write(s, 0, 7);
+                RawMutSlice::write(s, 1, 8);
+                let x = realloc_array(x, 2, 6);
+                let s = ptr::slice_from_raw_parts(x, 6);
+                assert_eq!(RawSlice::read(s, 0), 7);
+                assert_eq!(RawSlice::read(s, 1), 8);
+                assert!(!s.is_poisoned());
+                assert!(s.slice_from(2).is_poisoned());
+                dealloc_array(x, 6);
+            }
+        }
+
+        #[test]
+        fn test_dealloc_poisons() {
+            unsafe {
+                let x = alloc_array_in::<u8, _>(&Leaky, 4);
+                ptr::write_bytes(x, 1, 4);
+                dealloc_array_in(&Leaky, x, 4);
+                // Leaky never really frees, so the memory may still be read.
+                assert_eq!(*(x as *const [u8; 4]), [FREE_POISON; 4]);
+            }
+        }
+
+        /// An allocator whose reallocs always fail.
+        struct NoRealloc;
+
+        unsafe impl RawAllocator for NoRealloc {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                Global.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                Global.dealloc(ptr, layout)
+            }
+
+            unsafe fn realloc(&self, _: *mut u8, _: Layout, _: usize) -> *mut u8 {
+                ptr::null_mut()
+            }
+        }
+
+        #[test]
+        fn test_failed_shrink_restores() {
+            unsafe {
+                let x = alloc_array_in::<u8, _>(&NoRealloc, 6);
+                ptr::copy_nonoverlapping([1, 2, 3, 4, 5, 6].as_ptr(), x, 6);
+                assert!(realloc_array_in(&NoRealloc, x, 6, 2).is_null());
+                assert_eq!(*(x as *const [u8; 6]), [1, 2, 3, 4, 5, 6]);
+                dealloc_array_in(&NoRealloc, x, 6);
+            }
+        }
+    }
+}
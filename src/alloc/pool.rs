@@ -0,0 +1,216 @@
+//! A pool of fixed-size slots, for many allocations of the same type that come and go.
+
+use std::cmp;
+use std::mem::{size_of, ManuallyDrop};
+use std::ptr::{self, NonNull};
+use super::lazy;
+
+/// The number of slots in the first slab of a `Pool` made with `new`.
+const MIN_SLAB_LEN: usize = 16;
+
+/// Hands out memory for single `T`s from large slabs, recycling freed slots through a free list.
+///
+/// Allocating and freeing are O(1), and only touch the global allocator when every slot is in
+/// use, in which case a new slab at least as large as all the others together is allocated.
+/// Slabs are only freed when the pool is dropped. Zero-sized types never allocate.
+///
+/// Slabs are allocated through `alloc::lazy`, so running out of memory invokes `alloc::oom`.
+///
+/// The pool never reads, writes or drops any `T`. Values stored in it that need dropping must be
+/// dropped by the user before their slot, or the pool, is freed.
+pub struct Pool<T> {
+    slabs: Vec<Slab<T>>,
+    /// The most recently freed slot, which links to the one freed before it.
+    free: *mut Slot<T>,
+    /// The number of slots at the start of the last slab that have ever been handed out.
+    fresh: usize,
+}
+
+struct Slab<T> {
+    ptr: NonNull<Slot<T>>,
+    len: usize,
+}
+
+/// A slot, which holds the next free slot while it's free.
+#[repr(C)]
+union Slot<T> {
+    value: ManuallyDrop<T>,
+    next: *mut Slot<T>,
+}
+
+unsafe impl<T: Send> Send for Pool<T> {}
+unsafe impl<T: Sync> Sync for Pool<T> {}
+
+impl<T> Pool<T> {
+    /// Makes a new pool without allocating.
+    pub fn new() -> Pool<T> {
+        Pool { slabs: Vec::new(), free: ptr::null_mut(), fresh: 0 }
+    }
+
+    /// Makes a new pool with a single slab of exactly `cap` slots.
+    pub fn with_capacity(cap: usize) -> Pool<T> {
+        let mut pool = Pool::new();
+        if size_of::<T>() != 0 && cap != 0 {
+            unsafe { pool.add_slab(cap); }
+        }
+        pool
+    }
+
+    /// Gets the number of slots the pool can hold without allocating another slab. This is
+    /// `usize::MAX` for zero-sized types.
+    pub fn capacity(&self) -> usize {
+        if size_of::<T>() == 0 {
+            usize::MAX
+        } else {
+            self.slabs.iter().map(|s| s.len).sum()
+        }
+    }
+
+    /// Gets the number of slabs the pool has allocated.
+    pub fn slab_count(&self) -> usize {
+        self.slabs.len()
+    }
+
+    /// Allocates and returns a ptr to a slot for a single `T`. Handles zero-sized types
+    /// automatically by returning a non-null dangling ptr.
+    pub fn alloc(&mut self) -> *mut T {
+        if size_of::<T>() == 0 {
+            return NonNull::dangling().as_ptr();
+        }
+        unsafe {
+            if !self.free.is_null() {
+                let slot = self.free;
+                self.free = (*slot).next;
+                return slot as *mut T;
+            }
+            match self.slabs.last() {
+                Some(slab) if self.fresh < slab.len => {}
+                _ => self.add_slab(cmp::max(self.capacity(), MIN_SLAB_LEN)),
+            }
+            let slab = self.slabs.last().unwrap();
+            let slot = slab.ptr.as_ptr().add(self.fresh);
+            self.fresh += 1;
+            slot as *mut T
+        }
+    }
+
+    /// Returns the slot referenced by `ptr` to the pool, to be handed out again by `alloc`.
+    ///
+    /// # Undefined Behaviour
+    ///
+    /// * `ptr` must have been returned by `alloc` on this pool, and not freed since.
+    pub unsafe fn dealloc(&mut self, ptr: *mut T) {
+        if size_of::<T>() == 0 {
+            return;
+        }
+        debug_assert!(self.owns(ptr), "ptr passed to Pool::dealloc is not from this pool");
+        let slot = ptr as *mut Slot<T>;
+        (*slot).next = self.free;
+        self.free = slot;
+    }
+
+    fn owns(&self, ptr: *mut T) -> bool {
+        self.slabs.iter().any(|s| {
+            let start = s.ptr.as_ptr() as usize;
+            let offset = (ptr as usize).wrapping_sub(start);
+            offset < s.len * size_of::<Slot<T>>() && offset.is_multiple_of(size_of::<Slot<T>>())
+        })
+    }
+
+    /// Allocates a new slab of `len` slots, and makes it the one fresh slots come from. Any fresh
+    /// slots left in the previous slab are given to the free list.
+    unsafe fn add_slab(&mut self, len: usize) {
+        if let Some(slab) = self.slabs.last() {
+            for i in self.fresh..slab.len {
+                let slot = slab.ptr.as_ptr().add(i);
+                (*slot).next = self.free;
+                self.free = slot;
+            }
+        }
+        let ptr = NonNull::new_unchecked(lazy::alloc_array::<Slot<T>>(len));
+        self.slabs.push(Slab { ptr, len });
+        self.fresh = 0;
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Pool<T> {
+        Pool::new()
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        for slab in &self.slabs {
+            unsafe { lazy::dealloc_array(slab.ptr.as_ptr(), slab.len); }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rawptr::{RawPtrExt, RawMutPtrExt};
+
+    #[test]
+    fn test_alloc_dealloc() {
+        let mut pool = Pool::<u64>::new();
+        assert_eq!((pool.capacity(), pool.slab_count()), (0, 0));
+        unsafe {
+            let ptrs: Vec<_> = (0..20).map(|i| {
+                let ptr = pool.alloc();
+                RawMutPtrExt::write(ptr, i);
+                ptr
+            }).collect();
+            assert_eq!((pool.capacity(), pool.slab_count()), (32, 2));
+            assert!(ptrs.iter().enumerate().all(|(i, &p)| RawPtrExt::read(p) == i as u64));
+
+            // Freed slots are reused most recent first, before any fresh ones.
+            pool.dealloc(ptrs[3]);
+            pool.dealloc(ptrs[17]);
+            assert_eq!(pool.alloc(), ptrs[17]);
+            assert_eq!(pool.alloc(), ptrs[3]);
+            assert_eq!(RawPtrExt::read(ptrs[4]), 4);
+            assert_eq!(pool.slab_count(), 2);
+        }
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut pool = Pool::<[u8; 3]>::with_capacity(5);
+        assert_eq!((pool.capacity(), pool.slab_count()), (5, 1));
+        let ptrs: Vec<_> = (0..5).map(|_| pool.alloc()).collect();
+        assert_eq!(pool.slab_count(), 1);
+        for w in ptrs.windows(2) {
+            assert!(w[0] != w[1]);
+        }
+        pool.alloc();
+        assert_eq!((pool.capacity(), pool.slab_count()), (21, 2));
+    }
+
+    #[test]
+    fn test_leftover_fresh_slots() {
+        let mut pool = Pool::<u32>::with_capacity(4);
+        let a = pool.alloc();
+        // Force a new slab while the first still has fresh slots.
+        unsafe { pool.add_slab(2); }
+        let mut ptrs: Vec<_> = (0..5).map(|_| pool.alloc()).collect();
+        ptrs.push(a);
+        ptrs.sort();
+        ptrs.dedup();
+        assert_eq!(ptrs.len(), 6);
+        assert_eq!(pool.slab_count(), 2);
+    }
+
+    #[test]
+    fn test_zst() {
+        let mut pool = Pool::<()>::with_capacity(10);
+        assert_eq!((pool.capacity(), pool.slab_count()), (usize::MAX, 0));
+        let ptr = pool.alloc();
+        assert_eq!(ptr, NonNull::dangling().as_ptr());
+        unsafe { pool.dealloc(ptr); }
+        assert_eq!(pool.slab_count(), 0);
+    }
+}
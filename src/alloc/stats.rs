@@ -0,0 +1,324 @@
+//! Introspection into the allocations made through this module.
+//!
+//! With the `debug-alloc` feature, every live allocation can be listed, which lets tests check
+//! that nothing allocated through `plain`, `lazy` or `checked` is leaked. With
+//! `debug-alloc-backtrace`, each allocation also carries a backtrace of where it was made.
+//!
+//! The list covers the whole process. Since the test harness runs tests in parallel, a test
+//! checking for its own leaks should use a `LeakCheck`, which only sees the allocations the test
+//! makes itself.
+//!
+//! With the `alloc-stats` feature, `snapshot` reads a set of running totals instead, which are
+//! cheap enough to leave enabled in production builds.
+//!
+//! Zero-sized allocations never reach the allocator, so they are neither tracked nor counted.
+
+#[cfg(feature = "debug-alloc")]
+use std::fmt::Write;
+#[cfg(feature = "debug-alloc-backtrace")]
+use std::backtrace::Backtrace;
+#[cfg(feature = "debug-alloc-backtrace")]
+use std::sync::Arc;
+#[cfg(feature = "debug-alloc")]
+use std::thread::{self, ThreadId};
+#[cfg(feature = "alloc-stats")]
+use super::counters;
+#[cfg(feature = "debug-alloc")]
+use super::tracker;
+
+/// Running totals of allocator traffic since the process started.
+///
+/// The counters are read one at a time, not atomically as a set, so while other threads are
+/// allocating they may not quite agree with each other: `bytes_freed` can even exceed
+/// `bytes_allocated`. Each one is accurate on its own.
+#[cfg(feature = "alloc-stats")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Total bytes handed out, including by resizing.
+    pub bytes_allocated: usize,
+    /// Total bytes given back, including by resizing.
+    pub bytes_freed: usize,
+    /// The bytes that are currently live.
+    pub live_bytes: usize,
+    /// The most bytes that were live at once.
+    pub peak_bytes: usize,
+    /// Calls to `realloc_array` and the other resizing functions, whether or not they succeeded.
+    pub reallocs: usize,
+    /// Calls to `try_grow_inplace` that yielded `Ok`.
+    pub grow_inplace_succeeded: usize,
+    /// Calls to `try_grow_inplace` that yielded `Err`.
+    pub grow_inplace_failed: usize,
+    /// Calls to `try_shrink_inplace` that yielded `Ok`.
+    pub shrink_inplace_succeeded: usize,
+    /// Calls to `try_shrink_inplace` that yielded `Err`.
+    pub shrink_inplace_failed: usize,
+}
+
+/// Reads the current totals.
+#[cfg(feature = "alloc-stats")]
+pub fn snapshot() -> AllocStats {
+    counters::snapshot()
+}
+
+/// An allocation that has not been freed yet.
+#[cfg(feature = "debug-alloc")]
+#[derive(Clone, Debug)]
+pub struct LiveAllocation {
+    /// The start of the allocation.
+    pub ptr: *mut u8,
+    /// The name of the type the allocation was made for. For allocations with a tail, this is the
+    /// header type.
+    pub type_name: &'static str,
+    /// The size of a single element, in bytes.
+    pub elem_size: usize,
+    /// The number of elements.
+    pub len: usize,
+    /// The alignment of the allocation.
+    pub align: usize,
+    /// The thread that made the allocation, or last resized it.
+    pub thread: ThreadId,
+    /// Orders allocations by when they were made, or last resized.
+    pub(crate) seq: u64,
+    /// Where the allocation was made, or last resized.
+    #[cfg(feature = "debug-alloc-backtrace")]
+    pub backtrace: Arc<Backtrace>,
+}
+
+#[cfg(feature = "debug-alloc")]
+impl LiveAllocation {
+    /// The size of the elements, in bytes. Allocations with a tail also have a header and padding
+    /// not counted here.
+    pub fn size(&self) -> usize {
+        self.elem_size * self.len
+    }
+}
+
+/// Lists every allocation that is currently live, sorted by type name.
+#[cfg(feature = "debug-alloc")]
+pub fn live_allocations() -> Vec<LiveAllocation> {
+    tracker::live_allocations()
+}
+
+/// Describes every allocation that is currently live, or returns `None` if there are none.
+///
+/// This includes allocations made by other threads, and so by other tests running in parallel.
+/// Use a `LeakCheck` to only see those of the current test.
+#[cfg(feature = "debug-alloc")]
+pub fn report_leaks() -> Option<String> {
+    format_report(&live_allocations())
+}
+
+/// Panics with the output of `report_leaks` if any allocation is still live. Like
+/// `report_leaks`, this sees every thread, so only use it in a test run with
+/// `--test-threads=1`, or at the end of a program.
+#[cfg(feature = "debug-alloc")]
+pub fn assert_no_leaks() {
+    if let Some(report) = report_leaks() {
+        panic!("{}", report);
+    }
+}
+
+/// A baseline for leak checking. It only sees allocations made on the thread that created it,
+/// after it was created, so a test that makes one at its start can check its own leaks while
+/// other tests run in parallel.
+///
+/// Allocations made before the baseline but resized after it count as new, and allocations made
+/// on other threads, including ones the test spawns, are never seen.
+#[cfg(feature = "debug-alloc")]
+#[derive(Clone, Debug)]
+pub struct LeakCheck {
+    since: u64,
+    thread: ThreadId,
+}
+
+#[cfg(feature = "debug-alloc")]
+impl LeakCheck {
+    /// Takes a baseline on the current thread.
+    pub fn new() -> LeakCheck {
+        LeakCheck { since: tracker::next_seq(), thread: thread::current().id() }
+    }
+
+    /// Lists the allocations made since the baseline that are still live, sorted by type name.
+    pub fn live_allocations(&self) -> Vec<LiveAllocation> {
+        let mut live = live_allocations();
+        live.retain(|a| a.thread == self.thread && a.seq >= self.since);
+        live
+    }
+
+    /// Describes the allocations made since the baseline that are still live, or returns `None`
+    /// if there are none.
+    pub fn report_leaks(&self) -> Option<String> {
+        format_report(&self.live_allocations())
+    }
+
+    /// Panics with the output of `report_leaks` if any allocation made since the baseline is
+    /// still live. Intended to be called at the end of a test.
+    pub fn assert_no_leaks(&self) {
+        if let Some(report) = self.report_leaks() {
+            panic!("{}", report);
+        }
+    }
+}
+
+#[cfg(feature = "debug-alloc")]
+impl Default for LeakCheck {
+    fn default() -> LeakCheck {
+        LeakCheck::new()
+    }
+}
+
+#[cfg(feature = "debug-alloc")]
+fn format_report(live: &[LiveAllocation]) -> Option<String> {
+    if live.is_empty() {
+        return None;
+    }
+    let total: usize = live.iter().map(LiveAllocation::size).sum();
+    let mut report = format!("{} live allocation(s), {} bytes:\n", live.len(), total);
+    for a in live {
+        // Writing to a `String` can't fail.
+        let _ = writeln!(report, "  {:p}: {} x {} ({} bytes)", a.ptr, a.len, a.type_name,
+                         a.size());
+        #[cfg(feature = "debug-alloc-backtrace")]
+        for line in a.backtrace.to_string().lines() {
+            let _ = writeln!(report, "      {}", line);
+        }
+    }
+    Some(report)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::alloc::lazy;
+
+    #[cfg(feature = "debug-alloc")]
+    fn find(ptr: *mut u8) -> Option<LiveAllocation> {
+        live_allocations().into_iter().find(|a| a.ptr == ptr)
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    fn test_live_allocations() {
+        unsafe {
+            let x = lazy::alloc_array::<u32>(10);
+            let a = find(x as *mut u8).unwrap();
+            assert_eq!(a.type_name, "u32");
+            assert_eq!((a.elem_size, a.len, a.align, a.size()), (4, 10, 4, 40));
+
+            let x = lazy::realloc_array(x, 10, 20);
+            assert_eq!(find(x as *mut u8).unwrap().len, 20);
+            lazy::dealloc_array(x, 20);
+            assert!(find(x as *mut u8).is_none());
+
+            let (h, _) = lazy::alloc_with_tail::<u64, u8>(3);
+            let a = find(h as *mut u8).unwrap();
+            assert_eq!(a.type_name, "u64");
+            assert_eq!(a.len, 3);
+            lazy::dealloc_with_tail::<u64, u8>(h, 3);
+
+            // Zero-sized allocations are never tracked.
+            let z = lazy::alloc::<()>();
+            assert!(find(z as *mut u8).is_none());
+        }
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    fn test_leak_check() {
+        use crate::alloc::test::panic_message;
+
+        unsafe {
+            let before = lazy::alloc::<u8>();
+            let check = LeakCheck::new();
+            assert_eq!(check.report_leaks(), None);
+
+            let x = lazy::alloc_array::<u16>(4);
+            let other = thread::spawn(|| lazy::alloc::<u32>() as usize).join().unwrap();
+            let live = check.live_allocations();
+            assert_eq!(live.len(), 1);
+            assert_eq!((live[0].ptr, live[0].thread), (x as *mut u8, thread::current().id()));
+            let msg = panic_message(|| check.assert_no_leaks());
+            assert!(msg.starts_with("1 live allocation(s), 8 bytes:\n"), "{}", msg);
+            assert!(msg.contains(&format!("  {:p}: 4 x u16 (8 bytes)\n", x)), "{}", msg);
+
+            lazy::dealloc_array(x, 4);
+            check.assert_no_leaks();
+            lazy::dealloc(other as *mut u32);
+            lazy::dealloc(before);
+        }
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    fn test_assert_no_leaks() {
+        use crate::alloc::test::panic_message;
+
+        // Other tests may or may not have live allocations, so only a leak of this test's own
+        // can be relied on to be reported.
+        unsafe {
+            let x = lazy::alloc_array::<[u8; 5]>(3);
+            let msg = panic_message(assert_no_leaks);
+            lazy::dealloc_array(x, 3);
+            assert!(msg.contains(&format!("  {:p}: 3 x [u8; 5] (15 bytes)\n", x)), "{}", msg);
+        }
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    fn test_report() {
+        assert_eq!(format_report(&[]), None);
+
+        unsafe {
+            let x = lazy::alloc_array::<[u16; 3]>(2);
+            let report = format_report(&[find(x as *mut u8).unwrap()]).unwrap();
+            lazy::dealloc_array(x, 2);
+            assert!(report.starts_with("1 live allocation(s), 12 bytes:\n"));
+            assert!(report.contains(&format!("  {:p}: 2 x [u16; 3] (12 bytes)\n", x)));
+        }
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    #[test]
+    fn test_counters() {
+        unsafe {
+            // Other tests allocate concurrently, so only lower bounds can be checked.
+            let before = snapshot();
+            let x = lazy::alloc_array::<u8>(1000);
+            let during = snapshot();
+            assert!(during.bytes_allocated >= before.bytes_allocated + 1000);
+            assert!(during.peak_bytes >= 1000);
+            assert!(during.live_bytes >= 1000);
+
+            assert_eq!(lazy::try_grow_inplace(x, 1000, 1000), Ok(()));
+            assert_eq!(lazy::try_grow_inplace(x, 1000, 2000), Err(()));
+            assert_eq!(lazy::try_shrink_inplace(x, 1000, 1000), Ok(()));
+            assert_eq!(lazy::try_shrink_inplace(x, 1000, 10), Err(()));
+            let x = lazy::realloc_array(x, 1000, 10);
+            lazy::dealloc_array(x, 10);
+
+            let after = snapshot();
+            assert!(after.bytes_freed >= before.bytes_freed + 1010);
+            assert!(after.bytes_allocated >= before.bytes_allocated + 1010);
+            assert!(after.reallocs > before.reallocs);
+            assert!(after.grow_inplace_succeeded > before.grow_inplace_succeeded);
+            assert!(after.grow_inplace_failed > before.grow_inplace_failed);
+            assert!(after.shrink_inplace_succeeded > before.shrink_inplace_succeeded);
+            assert!(after.shrink_inplace_failed > before.shrink_inplace_failed);
+        }
+    }
+
+    #[cfg(feature = "debug-alloc-backtrace")]
+    #[test]
+    fn test_backtrace() {
+        use std::backtrace::BacktraceStatus;
+        unsafe {
+            let x = lazy::alloc::<u64>();
+            let a = find(x as *mut u8).unwrap();
+            lazy::dealloc(x);
+            assert_eq!(a.backtrace.status(), BacktraceStatus::Captured);
+            assert!(format_report(&[a]).unwrap().lines().count() > 2);
+        }
+    }
+}
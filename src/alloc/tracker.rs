@@ -0,0 +1,273 @@
+//! The side table behind the `debug-alloc` feature.
+//!
+//! Every allocation made through `plain` (and so through `lazy` and `checked`) is recorded here
+//! with the type, element size, length and alignment it was made with, the thread that made it,
+//! and optionally a backtrace. Calls that take an existing allocation check their arguments
+//! against the table, and panic if they don't match, if the ptr was never allocated, or if it was
+//! already freed. Zero-sized allocations are never recorded, since they don't have a unique
+//! address.
+//!
+//! Without the feature, all of these functions compile to nothing.
+
+#[cfg(feature = "debug-alloc")]
+pub use self::imp::*;
+
+#[cfg(not(feature = "debug-alloc"))]
+pub use self::noop::*;
+
+#[cfg(feature = "debug-alloc")]
+mod imp {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Mutex, MutexGuard};
+    use std::thread::{self, ThreadId};
+    #[cfg(feature = "debug-alloc-backtrace")]
+    use std::sync::Arc;
+    use crate::alloc::stats::LiveAllocation;
+
+    /// What an allocation was made with, and where.
+    #[derive(Clone, Debug)]
+    struct Record {
+        type_name: &'static str,
+        elem_size: usize,
+        len: usize,
+        align: usize,
+        seq: u64,
+        thread: ThreadId,
+        #[cfg(feature = "debug-alloc-backtrace")]
+        backtrace: Arc<std::backtrace::Backtrace>,
+    }
+
+    /// The record of an allocation that is being freed, in case it needs to be restored.
+    pub struct Released(Option<Record>);
+
+    #[derive(Default)]
+    struct Table {
+        live: HashMap<usize, Record>,
+        freed: HashSet<usize>,
+    }
+
+    static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+    /// The sequence number of the next allocation, so allocations can be ordered in time.
+    static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    fn table() -> MutexGuard<'static, Option<Table>> {
+        // A panic never happens while the lock is held, but don't let a poisoned lock hide
+        // the real error if one does.
+        TABLE.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn insert(ptr: *mut u8, record: Record) {
+        let mut guard = table();
+        let table = guard.get_or_insert_with(Table::default);
+        table.freed.remove(&(ptr as usize));
+        table.live.insert(ptr as usize, record);
+    }
+
+    /// Records a new allocation of `len` elements of `elem_size` bytes, described by `type_name`.
+    /// `null` ptrs are ignored.
+    pub fn on_alloc(ptr: *mut u8, type_name: &'static str, elem_size: usize, len: usize,
+                    align: usize) {
+        if ptr.is_null() {
+            return;
+        }
+        insert(ptr, Record {
+            type_name,
+            elem_size,
+            len,
+            align,
+            seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+            thread: thread::current().id(),
+            #[cfg(feature = "debug-alloc-backtrace")]
+            backtrace: Arc::new(std::backtrace::Backtrace::force_capture()),
+        });
+    }
+
+    /// Gets the sequence number the next allocation will be recorded with. Every allocation
+    /// recorded after this call has a number at least this large.
+    pub fn next_seq() -> u64 {
+        NEXT_SEQ.load(Ordering::Relaxed)
+    }
+
+    /// Checks that `ptr` is a live allocation made with the given element size, length and
+    /// alignment, panicking with a description of the mismatch if not. `op` names the function
+    /// being checked.
+    pub fn check(op: &str, ptr: *mut u8, elem_size: usize, len: usize, align: usize) {
+        let result = {
+            let mut guard = table();
+            let table = guard.get_or_insert_with(Table::default);
+            find(table, op, ptr, elem_size, len, align)
+        };
+        if let Err(msg) = result {
+            panic!("{}", msg);
+        }
+    }
+
+    /// Like `check`, but also records that `ptr` is being freed. This must happen before the
+    /// memory is actually given back, so that another thread can't be handed the same address
+    /// first. If giving it back then fails, the caller must `restore` it.
+    pub fn release(op: &str, ptr: *mut u8, elem_size: usize, len: usize,
+                   align: usize) -> Released {
+        let result = {
+            let mut guard = table();
+            let table = guard.get_or_insert_with(Table::default);
+            find(table, op, ptr, elem_size, len, align).map(|()| {
+                table.freed.insert(ptr as usize);
+                table.live.remove(&(ptr as usize))
+            })
+        };
+        match result {
+            Ok(record) => Released(record),
+            Err(msg) => panic!("{}", msg),
+        }
+    }
+
+    /// Undoes a `release` of `ptr` whose memory turned out not to be freed.
+    pub fn restore(ptr: *mut u8, released: Released) {
+        if let Some(record) = released.0 {
+            insert(ptr, record);
+        }
+    }
+
+    /// Lists every allocation currently recorded.
+    pub fn live_allocations() -> Vec<LiveAllocation> {
+        let guard = table();
+        let mut live: Vec<_> = guard.iter().flat_map(|t| t.live.iter()).map(|(&ptr, r)| {
+            LiveAllocation {
+                ptr: ptr as *mut u8,
+                type_name: r.type_name,
+                elem_size: r.elem_size,
+                len: r.len,
+                align: r.align,
+                thread: r.thread,
+                seq: r.seq,
+                #[cfg(feature = "debug-alloc-backtrace")]
+                backtrace: r.backtrace.clone(),
+            }
+        }).collect();
+        live.sort_by_key(|a| (a.type_name, a.ptr as usize));
+        live
+    }
+
+    fn find(table: &Table, op: &str, ptr: *mut u8, elem_size: usize, len: usize,
+            align: usize) -> Result<(), String> {
+        match table.live.get(&(ptr as usize)) {
+            Some(r) if r.elem_size != elem_size || r.align != align => {
+                Err(format!("{}: ptr {:p} was allocated with elements of size {} and align {}, \
+                             but size {} and align {} were given",
+                            op, ptr, r.elem_size, r.align, elem_size, align))
+            }
+            Some(r) if r.len != len => {
+                Err(format!("{}: ptr {:p} was allocated with len {}, but len {} was given",
+                            op, ptr, r.len, len))
+            }
+            Some(_) => Ok(()),
+            None if table.freed.contains(&(ptr as usize)) => {
+                Err(format!("{}: ptr {:p} was already freed", op, ptr))
+            }
+            None => Err(format!("{}: ptr {:p} was not allocated by this crate", op, ptr)),
+        }
+    }
+}
+
+#[cfg(not(feature = "debug-alloc"))]
+#[allow(unused_variables)]
+mod noop {
+    pub struct Released;
+
+    #[inline(always)]
+    pub fn on_alloc(ptr: *mut u8, type_name: &'static str, elem_size: usize, len: usize,
+                    align: usize) {}
+
+    #[inline(always)]
+    pub fn check(op: &str, ptr: *mut u8, elem_size: usize, len: usize, align: usize) {}
+
+    #[inline(always)]
+    pub fn release(op: &str, ptr: *mut u8, elem_size: usize, len: usize,
+                   align: usize) -> Released {
+        Released
+    }
+
+    #[inline(always)]
+    pub fn restore(ptr: *mut u8, released: Released) {}
+}
+
+
+#[cfg(all(test, feature = "debug-alloc"))]
+mod test {
+    use crate::alloc::plain::*;
+    use crate::alloc::test::{panic_message, Leaky};
+
+    #[test]
+    fn test_wrong_len() {
+        unsafe {
+            let x = alloc_array::<u32>(4);
+            let msg = panic_message(|| { realloc_array(x, 5, 10); });
+            assert_eq!(msg, format!("realloc_array: ptr {:p} was allocated with len 4, \
+                                     but len 5 was given", x));
+            let msg = panic_message(|| { dealloc_array(x, 3); });
+            assert_eq!(msg, format!("dealloc_array: ptr {:p} was allocated with len 4, \
+                                     but len 3 was given", x));
+            let msg = panic_message(|| { let _ = try_grow_inplace(x, 2, 4); });
+            assert_eq!(msg, format!("try_grow_inplace: ptr {:p} was allocated with len 4, \
+                                     but len 2 was given", x));
+            let msg = panic_message(|| { let _ = try_shrink_inplace(x, 8, 4); });
+            assert_eq!(msg, format!("try_shrink_inplace: ptr {:p} was allocated with len 4, \
+                                     but len 8 was given", x));
+
+            // None of the failed calls disturbed the allocation.
+            let x = realloc_array(x, 4, 10);
+            assert_eq!(try_grow_inplace(x, 10, 10), Ok(()));
+            dealloc_array(x, 10);
+        }
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        unsafe {
+            let x = alloc_array::<u32>(4);
+            let msg = panic_message(|| dealloc_array(x as *mut u16, 8));
+            assert_eq!(msg, format!("dealloc_array: ptr {:p} was allocated with elements of \
+                                     size 4 and align 4, but size 2 and align 2 were given", x));
+            dealloc_array(x, 4);
+        }
+    }
+
+    #[test]
+    fn test_double_free() {
+        unsafe {
+            let x = alloc_array_in::<u64, _>(&Leaky, 2);
+            dealloc_array_in(&Leaky, x, 2);
+            let msg = panic_message(|| dealloc_array_in(&Leaky, x, 2));
+            assert_eq!(msg, format!("dealloc_array: ptr {:p} was already freed", x));
+            let msg = panic_message(|| { realloc_array_in(&Leaky, x, 2, 4); });
+            assert_eq!(msg, format!("realloc_array: ptr {:p} was already freed", x));
+            let msg = panic_message(|| { let _ = try_shrink_inplace(x, 2, 1); });
+            assert_eq!(msg, format!("try_shrink_inplace: ptr {:p} was already freed", x));
+        }
+    }
+
+    #[test]
+    fn test_unknown_ptr() {
+        unsafe {
+            let mut local = [0u8; 4];
+            let x = local.as_mut_ptr();
+            let msg = panic_message(|| dealloc_array(x, 4));
+            assert_eq!(msg, format!("dealloc_array: ptr {:p} was not allocated by this crate", x));
+        }
+    }
+
+    #[test]
+    fn test_realloc_moves_entry() {
+        unsafe {
+            let x = alloc_array_in::<u8, _>(&Leaky, 1);
+            // `Leaky` uses the default copying realloc, so this always moves.
+            let y = realloc_array_in(&Leaky, x, 1, 2);
+            assert!(x != y);
+            let msg = panic_message(|| dealloc_array_in(&Leaky, x, 1));
+            assert_eq!(msg, format!("dealloc_array: ptr {:p} was already freed", x));
+            dealloc_array_in(&Leaky, y, 2);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Reserving address space up front and committing memory to it page by page.
+//!
+//! `reserve` maps a range of inaccessible address space without using any memory. Pages of it are
+//! made usable with `commit`, given back to the OS with `decommit`, and the whole range is
+//! unmapped with `release`. Since committing never moves anything, a buffer built on a
+//! reservation can grow in place up to the size reserved, which `reserve_array`,
+//! `realloc_array` and `release_array` wrap up for arrays of `T`.
+//!
+//! These functions talk to the OS directly, so allocations made with them are invisible to the
+//! debugging features of `plain`. All sizes are rounded up to whole pages.
+//!
+//! Only available on Linux.
+
+use std::mem::size_of;
+use std::ptr::{self, NonNull};
+
+/// Gets the size of a page, the granularity of every function in this module.
+pub fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Rounds `bytes` up to a whole number of pages. Returns `None` on overflow.
+fn round_to_pages(bytes: usize) -> Option<usize> {
+    let page = page_size();
+    bytes.checked_add(page - 1).map(|n| n & !(page - 1))
+}
+
+/// Reserves `bytes` of address space, and returns a page-aligned ptr to the start of it. None of
+/// it is usable until committed. Returns `null` if the address space couldn't be reserved, or if
+/// `bytes` overflows when rounded up to a whole number of pages.
+///
+/// # Undefined Behaviour
+///
+/// * `bytes` must not be 0.
+pub unsafe fn reserve(bytes: usize) -> *mut u8 {
+    debug_assert!(bytes != 0, "0 bytes passed to reserve");
+    let bytes = match round_to_pages(bytes) {
+        Some(bytes) => bytes,
+        None => return ptr::null_mut(),
+    };
+    // Inaccessible private mappings aren't charged against the commit limit, so this costs no
+    // memory. Not passing `MAP_NORESERVE` means `commit` is charged instead, and can fail.
+    let ptr = libc::mmap(ptr::null_mut(), bytes, libc::PROT_NONE,
+                         libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+    if ptr == libc::MAP_FAILED {
+        ptr::null_mut()
+    } else {
+        ptr as *mut u8
+    }
+}
+
+/// Makes the pages covering `bytes` bytes from `ptr` readable and writable. Pages committed for
+/// the first time, or since they were decommitted, are zeroed. Returns `Err` if the OS refuses,
+/// which it does if committing would exceed the system's commit limit.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must be page-aligned.
+/// * The pages must lie within a single reservation made by `reserve`.
+pub unsafe fn commit(ptr: *mut u8, bytes: usize) -> Result<(), ()> {
+    debug_assert!((ptr as usize).is_multiple_of(page_size()), "unaligned ptr passed to commit");
+    let bytes = round_to_pages(bytes).unwrap();
+    if libc::mprotect(ptr as *mut _, bytes, libc::PROT_READ | libc::PROT_WRITE) == 0 {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+/// Gives the memory of the pages covering `bytes` bytes from `ptr` back to the OS, and makes them
+/// inaccessible again. The address space stays reserved, and stays charged against the commit
+/// limit until it is released.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must be page-aligned.
+/// * The pages must lie within a single reservation made by `reserve`.
+pub unsafe fn decommit(ptr: *mut u8, bytes: usize) {
+    debug_assert!((ptr as usize).is_multiple_of(page_size()), "unaligned ptr passed to decommit");
+    let bytes = round_to_pages(bytes).unwrap();
+    libc::madvise(ptr as *mut _, bytes, libc::MADV_DONTNEED);
+    libc::mprotect(ptr as *mut _, bytes, libc::PROT_NONE);
+}
+
+/// Unmaps a whole reservation, committed or not.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been returned by `reserve`, and `bytes` must be the `bytes` passed to it.
+pub unsafe fn release(ptr: *mut u8, bytes: usize) {
+    libc::munmap(ptr as *mut _, round_to_pages(bytes).unwrap());
+}
+
+/// Reserves room for up to `max_len` elements of type T, none of which are committed. Handles
+/// zero-sized types automatically by returning a non-null dangling ptr. Returns `null` if the
+/// address space couldn't be reserved, or if the requested size overflows.
+///
+/// # Undefined Behaviour
+///
+/// * `max_len` must not be 0.
+pub unsafe fn reserve_array<T>(max_len: usize) -> *mut T {
+    debug_assert!(max_len != 0, "0 len passed to reserve_array");
+    if size_of::<T>() == 0 {
+        return NonNull::dangling().as_ptr();
+    }
+    match size_of::<T>().checked_mul(max_len) {
+        Some(bytes) if bytes <= isize::MAX as usize => reserve(bytes) as *mut T,
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Resizes the committed part of the array referenced by `ptr` from `old_len` to `len` elements of
+/// type T, committing or decommitting whole pages as needed. The array never moves, so unlike
+/// `plain::realloc_array`, ptrs into it stay valid. Handles zero-sized types automatically by
+/// doing nothing. Returns `Err` if `len` is more than `max_len`, or if the OS refuses to commit
+/// more memory, in which case nothing changed.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been returned by `reserve_array` with `max_len`.
+/// * `old_len` must be the `len` of the last call to `realloc_array` on `ptr`, or 0 if there
+///   was none.
+pub unsafe fn realloc_array<T>(ptr: *mut T, max_len: usize, old_len: usize,
+                               len: usize) -> Result<(), ()> {
+    debug_assert!(old_len <= max_len, "old_len larger than max_len in realloc_array");
+    if len > max_len {
+        return Err(());
+    }
+    if size_of::<T>() == 0 {
+        return Ok(());
+    }
+    let ptr = ptr as *mut u8;
+    let old_end = round_to_pages(old_len * size_of::<T>()).unwrap();
+    let new_end = round_to_pages(len * size_of::<T>()).unwrap();
+    if new_end > old_end {
+        commit(ptr.add(old_end), new_end - old_end)
+    } else {
+        if new_end < old_end {
+            decommit(ptr.add(new_end), old_end - new_end);
+        }
+        Ok(())
+    }
+}
+
+/// Unmaps the array referenced by `ptr`. Handles zero-sized types automatically by doing nothing.
+///
+/// # Undefined Behaviour
+///
+/// * `ptr` must have been returned by `reserve_array` with `max_len`.
+pub unsafe fn release_array<T>(ptr: *mut T, max_len: usize) {
+    if size_of::<T>() != 0 {
+        release(ptr as *mut u8, max_len * size_of::<T>());
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rawptr::{RawPtrExt, RawMutPtrExt};
+
+    #[test]
+    fn test_commit_decommit() {
+        unsafe {
+            let page = page_size();
+            let ptr = reserve(page * 16);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % page, 0);
+            assert_eq!(commit(ptr.add(page * 2), page * 2), Ok(()));
+            RawMutPtrExt::write(ptr.add(page * 2), 7);
+            RawMutPtrExt::write(ptr.add(page * 4 - 1), 8);
+            assert_eq!(RawPtrExt::read(ptr.add(page * 3 + 1)), 0);
+
+            decommit(ptr.add(page * 2), page);
+            assert_eq!(RawPtrExt::read(ptr.add(page * 4 - 1)), 8);
+            assert_eq!(commit(ptr.add(page * 2), page), Ok(()));
+            assert_eq!(RawPtrExt::read(ptr.add(page * 2)), 0);
+            release(ptr, page * 16);
+        }
+    }
+
+    #[test]
+    fn test_realloc_array_in_place() {
+        unsafe {
+            let max_len = 1 << 20;
+            let ptr = reserve_array::<u64>(max_len);
+            assert!(!ptr.is_null());
+            assert_eq!(realloc_array(ptr, max_len, 0, 10), Ok(()));
+            for i in 0..10 {
+                RawMutPtrExt::write(ptr.add(i), i as u64);
+            }
+            assert_eq!(realloc_array(ptr, max_len, 10, 100_000), Ok(()));
+            RawMutPtrExt::write(ptr.add(99_999), 1);
+            assert!((0..10).all(|i| RawPtrExt::read(ptr.add(i)) == i as u64));
+
+            assert_eq!(realloc_array(ptr, max_len, 100_000, max_len + 1), Err(()));
+            assert_eq!(realloc_array(ptr, max_len, 100_000, 5), Ok(()));
+            assert!((0..5).all(|i| RawPtrExt::read(ptr.add(i)) == i as u64));
+            assert_eq!(realloc_array(ptr, max_len, 5, max_len), Ok(()));
+            assert_eq!(RawPtrExt::read(ptr.add(99_999)), 0);
+            release_array(ptr, max_len);
+        }
+    }
+
+    #[test]
+    fn test_zst_and_overflow() {
+        unsafe {
+            let ptr = reserve_array::<()>(usize::MAX);
+            assert_eq!(ptr, NonNull::dangling().as_ptr());
+            assert_eq!(realloc_array(ptr, usize::MAX, 0, usize::MAX), Ok(()));
+            release_array(ptr, usize::MAX);
+
+            // Zero-sized types still can't grow past `max_len`.
+            let ptr = reserve_array::<()>(5);
+            assert_eq!(realloc_array(ptr, 5, 0, 6), Err(()));
+            assert_eq!(realloc_array(ptr, 5, 0, 5), Ok(()));
+            release_array(ptr, 5);
+
+            assert!(reserve_array::<u32>(usize::MAX / 2).is_null());
+            assert!(reserve(usize::MAX).is_null());
+        }
+    }
+}
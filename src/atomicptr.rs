@@ -0,0 +1,379 @@
+//! Atomic operations directly on raw pointers to integers and pointers.
+//!
+//! Each operation borrows the memory as the matching `std::sync::atomic` type, via its
+//! `from_ptr`, for exactly the duration of that one operation, so no reference outlives the
+//! call to alias anything else.
+
+use std::mem::align_of;
+use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicI8, AtomicI16, AtomicI32, AtomicIsize, AtomicPtr};
+use std::sync::atomic::{AtomicU8, AtomicU16, AtomicU32, AtomicUsize};
+#[cfg(target_has_atomic = "64")]
+use std::sync::atomic::{AtomicI64, AtomicU64};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The types `AtomicPtrExt` can operate on: integers and raw pointers, on targets with atomics of
+/// their size. This trait is sealed.
+pub unsafe trait AtomicPrimitive: Copy + private::Sealed {
+    #[doc(hidden)]
+    unsafe fn load(ptr: *mut Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn store(ptr: *mut Self, val: Self, order: Ordering);
+    #[doc(hidden)]
+    unsafe fn swap(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn compare_exchange(ptr: *mut Self, current: Self, new: Self, success: Ordering,
+                               failure: Ordering) -> Result<Self, Self>;
+    #[doc(hidden)]
+    unsafe fn compare_exchange_weak(ptr: *mut Self, current: Self, new: Self, success: Ordering,
+                                    failure: Ordering) -> Result<Self, Self>;
+}
+
+/// The integer types `AtomicIntPtrExt` can operate on. This trait is sealed.
+pub unsafe trait AtomicInteger: AtomicPrimitive {
+    #[doc(hidden)]
+    unsafe fn fetch_add(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn fetch_sub(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn fetch_and(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn fetch_or(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn fetch_xor(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn fetch_max(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+    #[doc(hidden)]
+    unsafe fn fetch_min(ptr: *mut Self, val: Self, order: Ordering) -> Self;
+}
+
+/// Extension trait for atomic operations on mutable raw pointers.
+///
+/// All of these behave like the methods of the same names, minus the `atomic_` prefix, on the
+/// `std::sync::atomic` type matching T.
+///
+/// # Undefined Behaviour
+///
+/// For every method:
+///
+/// * `self` must be valid for reads and writes, and aligned to the alignment of the matching
+///   atomic type, which may be larger than T's own.
+/// * While the operation runs, the memory must not be accessed non-atomically from another thread,
+///   nor atomically with a different size.
+pub trait AtomicPtrExt<T> {
+    /// Loads the value at `self`.
+    unsafe fn atomic_load(self, order: Ordering) -> T;
+
+    /// Stores `val` at `self`.
+    unsafe fn atomic_store(self, val: T, order: Ordering);
+
+    /// Stores `val` at `self`, returning the previous value.
+    unsafe fn atomic_swap(self, val: T, order: Ordering) -> T;
+
+    /// Stores `new` at `self` if the value there is `current`. Returns the previous value, as
+    /// `Ok` if it was `current` and `Err` if not.
+    unsafe fn atomic_compare_exchange(self, current: T, new: T, success: Ordering,
+                                      failure: Ordering) -> Result<T, T>;
+
+    /// Like `atomic_compare_exchange`, but may fail spuriously, which can be cheaper in a loop.
+    unsafe fn atomic_compare_exchange_weak(self, current: T, new: T, success: Ordering,
+                                           failure: Ordering) -> Result<T, T>;
+}
+
+/// Extension trait for atomic arithmetic on mutable raw pointers to integers.
+///
+/// Each method stores the result of combining the value at `self` with `val`, and returns the
+/// previous value. Addition and subtraction wrap around on overflow.
+///
+/// # Undefined Behaviour
+///
+/// As for `AtomicPtrExt`.
+pub trait AtomicIntPtrExt<T> {
+    /// Adds `val` to the value at `self`.
+    unsafe fn atomic_fetch_add(self, val: T, order: Ordering) -> T;
+
+    /// Subtracts `val` from the value at `self`.
+    unsafe fn atomic_fetch_sub(self, val: T, order: Ordering) -> T;
+
+    /// Bitwise ands `val` into the value at `self`.
+    unsafe fn atomic_fetch_and(self, val: T, order: Ordering) -> T;
+
+    /// Bitwise ors `val` into the value at `self`.
+    unsafe fn atomic_fetch_or(self, val: T, order: Ordering) -> T;
+
+    /// Bitwise xors `val` into the value at `self`.
+    unsafe fn atomic_fetch_xor(self, val: T, order: Ordering) -> T;
+
+    /// Stores the maximum of `val` and the value at `self`.
+    unsafe fn atomic_fetch_max(self, val: T, order: Ordering) -> T;
+
+    /// Stores the minimum of `val` and the value at `self`.
+    unsafe fn atomic_fetch_min(self, val: T, order: Ordering) -> T;
+}
+
+impl<T: AtomicPrimitive> AtomicPtrExt<T> for *mut T {
+    unsafe fn atomic_load(self, order: Ordering) -> T {
+        T::load(self, order)
+    }
+
+    unsafe fn atomic_store(self, val: T, order: Ordering) {
+        T::store(self, val, order)
+    }
+
+    unsafe fn atomic_swap(self, val: T, order: Ordering) -> T {
+        T::swap(self, val, order)
+    }
+
+    unsafe fn atomic_compare_exchange(self, current: T, new: T, success: Ordering,
+                                      failure: Ordering) -> Result<T, T> {
+        T::compare_exchange(self, current, new, success, failure)
+    }
+
+    unsafe fn atomic_compare_exchange_weak(self, current: T, new: T, success: Ordering,
+                                           failure: Ordering) -> Result<T, T> {
+        T::compare_exchange_weak(self, current, new, success, failure)
+    }
+}
+
+impl<T: AtomicInteger> AtomicIntPtrExt<T> for *mut T {
+    unsafe fn atomic_fetch_add(self, val: T, order: Ordering) -> T {
+        T::fetch_add(self, val, order)
+    }
+
+    unsafe fn atomic_fetch_sub(self, val: T, order: Ordering) -> T {
+        T::fetch_sub(self, val, order)
+    }
+
+    unsafe fn atomic_fetch_and(self, val: T, order: Ordering) -> T {
+        T::fetch_and(self, val, order)
+    }
+
+    unsafe fn atomic_fetch_or(self, val: T, order: Ordering) -> T {
+        T::fetch_or(self, val, order)
+    }
+
+    unsafe fn atomic_fetch_xor(self, val: T, order: Ordering) -> T {
+        T::fetch_xor(self, val, order)
+    }
+
+    unsafe fn atomic_fetch_max(self, val: T, order: Ordering) -> T {
+        T::fetch_max(self, val, order)
+    }
+
+    unsafe fn atomic_fetch_min(self, val: T, order: Ordering) -> T {
+        T::fetch_min(self, val, order)
+    }
+}
+
+/// With debug assertions enabled, checks that `ptr` is aligned for the atomic type `A`. `op`
+/// names the method being checked.
+#[inline]
+fn check_align<A, T>(op: &str, ptr: *mut T) {
+    debug_assert!((ptr as usize).is_multiple_of(align_of::<A>()),
+                  "{}: ptr {:p} is not aligned to {}", op, ptr, align_of::<A>());
+}
+
+/// Implements `AtomicPrimitive` for `$t`, with generic parameters `$gen`, through
+/// `$atomic::from_ptr`.
+macro_rules! atomic_primitive {
+    (<$($gen:ident),*> $t:ty => $atomic:ty) => {
+        unsafe impl<$($gen),*> AtomicPrimitive for $t {
+            unsafe fn load(ptr: *mut Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_load", ptr);
+                <$atomic>::from_ptr(ptr).load(order)
+            }
+
+            unsafe fn store(ptr: *mut Self, val: Self, order: Ordering) {
+                check_align::<$atomic, _>("atomic_store", ptr);
+                <$atomic>::from_ptr(ptr).store(val, order)
+            }
+
+            unsafe fn swap(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_swap", ptr);
+                <$atomic>::from_ptr(ptr).swap(val, order)
+            }
+
+            unsafe fn compare_exchange(ptr: *mut Self, current: Self, new: Self,
+                                       success: Ordering,
+                                       failure: Ordering) -> Result<Self, Self> {
+                check_align::<$atomic, _>("atomic_compare_exchange", ptr);
+                <$atomic>::from_ptr(ptr).compare_exchange(current, new, success, failure)
+            }
+
+            unsafe fn compare_exchange_weak(ptr: *mut Self, current: Self, new: Self,
+                                            success: Ordering,
+                                            failure: Ordering) -> Result<Self, Self> {
+                check_align::<$atomic, _>("atomic_compare_exchange_weak", ptr);
+                <$atomic>::from_ptr(ptr).compare_exchange_weak(current, new, success, failure)
+            }
+        }
+    };
+}
+
+/// Implements `AtomicPrimitive` and `AtomicInteger` for each integer type and its atomic type.
+macro_rules! atomic_integers {
+    ($($(#[$attr:meta])* $t:ty => $atomic:ty;)*) => {$(
+        $(#[$attr])*
+        impl private::Sealed for $t {}
+
+        $(#[$attr])*
+        atomic_primitive!(<> $t => $atomic);
+
+        $(#[$attr])*
+        unsafe impl AtomicInteger for $t {
+            unsafe fn fetch_add(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_add", ptr);
+                <$atomic>::from_ptr(ptr).fetch_add(val, order)
+            }
+
+            unsafe fn fetch_sub(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_sub", ptr);
+                <$atomic>::from_ptr(ptr).fetch_sub(val, order)
+            }
+
+            unsafe fn fetch_and(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_and", ptr);
+                <$atomic>::from_ptr(ptr).fetch_and(val, order)
+            }
+
+            unsafe fn fetch_or(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_or", ptr);
+                <$atomic>::from_ptr(ptr).fetch_or(val, order)
+            }
+
+            unsafe fn fetch_xor(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_xor", ptr);
+                <$atomic>::from_ptr(ptr).fetch_xor(val, order)
+            }
+
+            unsafe fn fetch_max(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_max", ptr);
+                <$atomic>::from_ptr(ptr).fetch_max(val, order)
+            }
+
+            unsafe fn fetch_min(ptr: *mut Self, val: Self, order: Ordering) -> Self {
+                check_align::<$atomic, _>("atomic_fetch_min", ptr);
+                <$atomic>::from_ptr(ptr).fetch_min(val, order)
+            }
+        }
+    )*};
+}
+
+atomic_integers! {
+    i8 => AtomicI8;
+    i16 => AtomicI16;
+    i32 => AtomicI32;
+    #[cfg(target_has_atomic = "64")]
+    i64 => AtomicI64;
+    isize => AtomicIsize;
+    u8 => AtomicU8;
+    u16 => AtomicU16;
+    u32 => AtomicU32;
+    #[cfg(target_has_atomic = "64")]
+    u64 => AtomicU64;
+    usize => AtomicUsize;
+}
+
+impl<U> private::Sealed for *mut U {}
+
+atomic_primitive!(<U> *mut U => AtomicPtr<U>);
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+    use std::sync::atomic::Ordering::*;
+    use std::thread;
+    use crate::alloc::lazy;
+
+    const THREADS: usize = 8;
+    const ITERS: usize = 10_000;
+
+    #[test]
+    fn test_single_thread() {
+        unsafe {
+            let x = &mut 5i32 as *mut i32;
+            assert_eq!(x.atomic_load(SeqCst), 5);
+            x.atomic_store(6, SeqCst);
+            assert_eq!(x.atomic_swap(7, SeqCst), 6);
+            assert_eq!(x.atomic_compare_exchange(6, 8, SeqCst, SeqCst), Err(7));
+            assert_eq!(x.atomic_compare_exchange(7, 8, SeqCst, SeqCst), Ok(7));
+            assert_eq!(x.atomic_fetch_add(2, SeqCst), 8);
+            assert_eq!(x.atomic_fetch_sub(20, SeqCst), 10);
+            assert_eq!(x.atomic_fetch_max(3, SeqCst), -10);
+            assert_eq!(x.atomic_fetch_min(-1, SeqCst), 3);
+            assert_eq!(x.atomic_fetch_or(0b110, SeqCst), -1);
+            assert_eq!(x.atomic_fetch_and(0b101, SeqCst), -1);
+            assert_eq!(x.atomic_fetch_xor(0b001, SeqCst), 0b101);
+            assert_eq!(*x, 0b100);
+
+            let mut a = 1u8;
+            let mut b = 2u8;
+            let p = &mut (&mut a as *mut u8) as *mut *mut u8;
+            assert_eq!(p.atomic_swap(&mut b, SeqCst), &mut a as *mut u8);
+            assert_eq!(p.atomic_load(SeqCst), &mut b as *mut u8);
+            assert_eq!(p.atomic_compare_exchange(ptr::null_mut(), &mut a, SeqCst, SeqCst),
+                       Err(&mut b as *mut u8));
+        }
+    }
+
+    #[test]
+    fn test_stress_counters() {
+        unsafe {
+            // Heap memory shared between threads only through raw pointers.
+            let counters = lazy::alloc_array_zeroed::<usize>(2);
+            let addr = counters as usize;
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(move || {
+                        let counters = addr as *mut usize;
+                        for _ in 0..ITERS {
+                            counters.atomic_fetch_add(1, Relaxed);
+                            // A CAS loop, incrementing the second counter by hand.
+                            let second = counters.add(1);
+                            let mut current = second.atomic_load(Relaxed);
+                            while let Err(actual) = second.atomic_compare_exchange_weak(
+                                    current, current + 1, Relaxed, Relaxed) {
+                                current = actual;
+                            }
+                        }
+                    });
+                }
+            });
+            assert_eq!(*counters, THREADS * ITERS);
+            assert_eq!(*counters.add(1), THREADS * ITERS);
+            lazy::dealloc_array(counters, 2);
+        }
+    }
+
+    #[test]
+    fn test_stress_spinlock() {
+        unsafe {
+            // A spinlock made of a flag, guarding a counter that is updated non-atomically.
+            let lock = lazy::alloc_zeroed::<u32>();
+            let data = lazy::alloc_zeroed::<u64>();
+            let (lock_addr, data_addr) = (lock as usize, data as usize);
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(move || {
+                        let (lock, data) = (lock_addr as *mut u32, data_addr as *mut u64);
+                        for _ in 0..ITERS {
+                            while lock.atomic_compare_exchange_weak(0, 1, Acquire, Relaxed)
+                                      .is_err() {}
+                            *data += 1;
+                            lock.atomic_store(0, Release);
+                        }
+                    });
+                }
+            });
+            assert_eq!(*data, (THREADS * ITERS) as u64);
+            lazy::dealloc(lock);
+            lazy::dealloc(data);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::ptr;
+use crate::rawptr::{RawPtrExt, RawMutPtrExt};
+use crate::rawslice::RawMutSlice;
+
+/// A raw pointer into a buffer that, with debug assertions enabled, knows the bounds of the buffer
+/// and panics on any arithmetic or access that leaves them.
+///
+/// `BoundedPtr` implements `RawPtrExt` and `RawMutPtrExt`, so code written against those traits
+/// can switch to it by changing a type, and get bounds checking in debug builds. In release
+/// builds it is just a `*mut T`.
+///
+/// Arithmetic may move the pointer anywhere from the start of the buffer to one past its end, but
+/// accesses must stay within it. Ptrs passed to `copy` and `swap` as the destination or other
+/// side aren't checked.
+#[cfg_attr(not(debug_assertions), repr(transparent))]
+pub struct BoundedPtr<T> {
+    ptr: *mut T,
+    #[cfg(debug_assertions)]
+    bounds: Bounds,
+}
+
+/// Where a `BoundedPtr` is within its buffer, in elements.
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone, Debug)]
+struct Bounds {
+    pos: usize,
+    len: usize,
+}
+
+impl<T> BoundedPtr<T> {
+    /// Makes a pointer to the start of the buffer of `len` elements at `base`.
+    pub fn new(base: *mut T, len: usize) -> BoundedPtr<T> {
+        #[cfg(not(debug_assertions))]
+        let _ = len;
+        BoundedPtr {
+            ptr: base,
+            #[cfg(debug_assertions)]
+            bounds: Bounds { pos: 0, len },
+        }
+    }
+
+    /// Makes a pointer to the start of a raw slice.
+    pub fn from_raw_slice(slice: *mut [T]) -> BoundedPtr<T> {
+        BoundedPtr::new(RawMutSlice::as_mut_ptr(slice), slice.len())
+    }
+
+    /// Gets the bare pointer.
+    pub fn as_ptr(self) -> *mut T {
+        self.ptr
+    }
+
+    /// Checks that the `count` elements from the current position are in bounds. `op` names the
+    /// method being checked.
+    #[inline]
+    fn check(self, op: &str, count: usize) {
+        #[cfg(debug_assertions)]
+        {
+            let Bounds { pos, len } = self.bounds;
+            if count > len - pos {
+                panic!("BoundedPtr::{}: {} elements at position {} are out of bounds of len {}",
+                       op, count, pos, len);
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = (op, count);
+    }
+}
+
+impl<T> Clone for BoundedPtr<T> {
+    fn clone(&self) -> BoundedPtr<T> {
+        *self
+    }
+}
+
+impl<T> Copy for BoundedPtr<T> {}
+
+impl<T> fmt::Debug for BoundedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("BoundedPtr");
+        s.field("ptr", &self.ptr);
+        #[cfg(debug_assertions)]
+        s.field("pos", &self.bounds.pos).field("len", &self.bounds.len);
+        s.finish()
+    }
+}
+
+impl<T> RawPtrExt<T> for BoundedPtr<T> {
+    fn as_raw_slice(self, len: usize) -> *const [T] {
+        self.check("as_raw_slice", len);
+        ptr::slice_from_raw_parts(self.ptr, len)
+    }
+
+    unsafe fn as_slice<'a>(self, len: usize) -> &'a [T] {
+        &*self.as_raw_slice(len)
+    }
+
+    unsafe fn add(self, count: usize) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            let Bounds { pos, len } = self.bounds;
+            if count > len - pos {
+                panic!("BoundedPtr::add: moving forward {} elements from position {} is out of \
+                        bounds of len {}", count, pos, len);
+            }
+        }
+        let mut result = self;
+        result.ptr = RawPtrExt::add(self.ptr, count);
+        #[cfg(debug_assertions)]
+        {
+            result.bounds.pos += count;
+        }
+        result
+    }
+
+    unsafe fn sub(self, count: usize) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            let Bounds { pos, len } = self.bounds;
+            if count > pos {
+                panic!("BoundedPtr::sub: moving back {} elements from position {} is out of \
+                        bounds of len {}", count, pos, len);
+            }
+        }
+        let mut result = self;
+        result.ptr = RawPtrExt::sub(self.ptr, count);
+        #[cfg(debug_assertions)]
+        {
+            result.bounds.pos -= count;
+        }
+        result
+    }
+
+    unsafe fn read(self) -> T {
+        self.check("read", 1);
+        RawPtrExt::read(self.ptr)
+    }
+
+    unsafe fn read_unaligned(self) -> T {
+        self.check("read_unaligned", 1);
+        RawPtrExt::read_unaligned(self.ptr)
+    }
+
+    unsafe fn read_volatile(self) -> T {
+        self.check("read_volatile", 1);
+        RawPtrExt::read_volatile(self.ptr)
+    }
+
+    unsafe fn copy(self, dest: *mut T, count: usize) {
+        self.check("copy", count);
+        RawPtrExt::copy(self.ptr, dest, count);
+    }
+
+    unsafe fn copy_nonoverlapping(self, dest: *mut T, count: usize) {
+        self.check("copy_nonoverlapping", count);
+        RawPtrExt::copy_nonoverlapping(self.ptr, dest, count);
+    }
+
+    unsafe fn copy_volatile(self, dest: *mut T, count: usize) {
+        self.check("copy_volatile", count);
+        RawPtrExt::copy_volatile(self.ptr, dest, count);
+    }
+}
+
+impl<T> RawMutPtrExt<T> for BoundedPtr<T> {
+    fn as_raw_mut_slice(self, len: usize) -> *mut [T] {
+        self.check("as_raw_mut_slice", len);
+        ptr::slice_from_raw_parts_mut(self.ptr, len)
+    }
+
+    unsafe fn as_mut_slice<'a>(self, len: usize) -> &'a mut [T] {
+        &mut *self.as_raw_mut_slice(len)
+    }
+
+    unsafe fn write(self, src: T) {
+        self.check("write", 1);
+        RawMutPtrExt::write(self.ptr, src);
+    }
+
+    unsafe fn write_unaligned(self, src: T) {
+        self.check("write_unaligned", 1);
+        RawMutPtrExt::write_unaligned(self.ptr, src);
+    }
+
+    unsafe fn write_volatile(self, src: T) {
+        self.check("write_volatile", 1);
+        RawMutPtrExt::write_volatile(self.ptr, src);
+    }
+
+    unsafe fn write_bytes(self, byte: u8, count: usize) {
+        self.check("write_bytes", count);
+        RawMutPtrExt::write_bytes(self.ptr, byte, count);
+    }
+
+    unsafe fn swap(self, y: *mut T) {
+        self.check("swap", 1);
+        RawMutPtrExt::swap(self.ptr, y);
+    }
+
+    unsafe fn replace(self, src: T) -> T {
+        self.check("replace", 1);
+        RawMutPtrExt::replace(self.ptr, src)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_access() {
+        let mut x = [1, 2, 3, 4];
+        let p = BoundedPtr::new(x.as_mut_ptr(), 4);
+        unsafe {
+            assert_eq!(p.add(3).read(), 4);
+            p.add(1).write(5);
+            assert_eq!(p.add(4).sub(3).replace(6), 5);
+            let mut y = 7;
+            p.add(2).swap(&mut y);
+            assert_eq!(y, 3);
+            p.add(2).copy(p.as_ptr(), 2);
+            assert_eq!(p.as_slice(4), &[7, 4, 7, 4]);
+            p.add(1).write_bytes(0, 3);
+            assert_eq!(x, [7, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_zst() {
+        let p = BoundedPtr::new(ptr::NonNull::<()>::dangling().as_ptr(), 2);
+        unsafe {
+            p.add(1).write(());
+            p.add(2).sub(2).read();
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_bounds() {
+        use crate::alloc::test::panic_message;
+
+        let mut x = [0u64; 4];
+        let p = BoundedPtr::from_raw_slice(&mut x[..] as *mut [u64]);
+        unsafe {
+            let msg = panic_message(|| { p.add(5); });
+            assert_eq!(msg, "BoundedPtr::add: moving forward 5 elements from position 0 is out \
+                             of bounds of len 4");
+            let msg = panic_message(|| { p.add(1).sub(2); });
+            assert_eq!(msg, "BoundedPtr::sub: moving back 2 elements from position 1 is out of \
+                             bounds of len 4");
+            let msg = panic_message(|| { p.add(4).read(); });
+            assert_eq!(msg, "BoundedPtr::read: 1 elements at position 4 are out of bounds of \
+                             len 4");
+            let msg = panic_message(|| p.add(2).copy(x.as_mut_ptr(), 3));
+            assert_eq!(msg, "BoundedPtr::copy: 3 elements at position 2 are out of bounds of \
+                             len 4");
+            let msg = panic_message(|| p.add(3).write_bytes(0, 2));
+            assert_eq!(msg, "BoundedPtr::write_bytes: 2 elements at position 3 are out of bounds \
+                             of len 4");
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn test_release_is_bare() {
+        use std::mem::size_of;
+        assert_eq!(size_of::<BoundedPtr<u64>>(), size_of::<*mut u64>());
+    }
+}
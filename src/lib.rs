@@ -8,9 +8,6 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-#![feature(core)]
-#![feature(alloc)]
-
 //! Utilities for working with the raw representations of core Rust data types.
 //!
 //! This library provides convenience methods and types for doing `unsafe` work in a more
@@ -26,8 +23,27 @@
 //! Note also that an *ideal* implementation of this library provides many of the offered methods
 //! as unsafe *operators*. Unfortunately, this is not currently possible in Rust as of this
 //! writing. We instead settle for named methods that mirror these operators.
+//!
+//! Raw pointers have since gained inherent methods named `add`, `sub`, `read`, `write`, `copy`
+//! and so on, which take precedence over the extension traits in method-call syntax. `p.add(n)`
+//! therefore calls std's method, and skips the checks this library performs with debug
+//! assertions enabled. To get them, call the trait methods explicitly, as in
+//! `RawPtrExt::add(p, n)` or `RawSlice::read(s, i)`, or use `boundedptr::BoundedPtr`, which has
+//! no inherent methods to shadow them.
+
+// The extension traits deliberately take raw pointers by value and document their contracts under
+// `# Undefined Behaviour`, so the corresponding style lints are not useful here.
+#![allow(clippy::missing_safety_doc)]
+#![allow(clippy::wrong_self_convention)]
+#![allow(clippy::len_without_is_empty)]
+#![allow(clippy::result_unit_err)]
 
 pub mod rawslice;
 pub mod rawptr;
+pub mod boundedptr;
+pub mod atomicptr;
 pub mod uncheckedslice;
 pub mod alloc;
+pub mod rawbuf;
+pub mod smallrawbuf;
+pub mod rawbox;
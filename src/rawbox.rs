@@ -0,0 +1,212 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr::{self, NonNull};
+use crate::alloc::lazy;
+
+/// An owned allocation for a single `T`, which may or may not be initialized.
+///
+/// The storage comes from `alloc::lazy::alloc`, so zero-sized types get a dangling pointer and
+/// never touch the allocator. Dropping a `RawBox` frees the storage, and also drops the value if
+/// it has been initialized.
+pub struct RawBox<T> {
+    ptr: NonNull<T>,
+    init: bool,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for RawBox<T> {}
+unsafe impl<T: Sync> Sync for RawBox<T> {}
+
+impl<T> RawBox<T> {
+    /// Allocates uninitialized storage for a `T`.
+    pub fn new_uninit() -> RawBox<T> {
+        unsafe {
+            RawBox {
+                ptr: NonNull::new_unchecked(lazy::alloc::<T>()),
+                init: false,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    /// Allocates storage for a `T` and initializes it with `value`.
+    pub fn new(value: T) -> RawBox<T> {
+        let mut b = RawBox::new_uninit();
+        b.write(value);
+        b
+    }
+
+    /// Takes ownership of storage allocated by `alloc::lazy::alloc`. `init` states whether it
+    /// holds an initialized value.
+    ///
+    /// # Undefined Behaviour
+    ///
+    /// * `ptr` must have been allocated by `alloc::lazy::alloc::<T>` or `alloc::plain::alloc::<T>`.
+    /// * `ptr` must not be owned by anything else.
+    /// * If `init` is true, `ptr` must point to a valid `T`.
+    pub unsafe fn from_raw(ptr: *mut T, init: bool) -> RawBox<T> {
+        RawBox { ptr: NonNull::new_unchecked(ptr), init, _marker: PhantomData }
+    }
+
+    /// Gives up ownership of the storage, returning a pointer to it. The caller becomes
+    /// responsible for dropping the value, if initialized, and freeing the storage with
+    /// `alloc::lazy::dealloc`.
+    pub fn into_raw(self) -> *mut T {
+        let ptr = self.ptr();
+        mem::forget(self);
+        ptr
+    }
+
+    /// Gets a pointer to the storage.
+    pub fn ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Whether the storage holds an initialized value.
+    pub fn is_init(&self) -> bool {
+        self.init
+    }
+
+    /// Stores `value`, dropping the previous value if there was one.
+    pub fn write(&mut self, value: T) {
+        unsafe {
+            if self.init {
+                // Cleared first, so a panicking destructor doesn't drop the value again in `drop`.
+                self.init = false;
+                ptr::drop_in_place(self.ptr());
+            }
+            ptr::write(self.ptr(), value);
+        }
+        self.init = true;
+    }
+
+    /// Declares that the storage has been initialized through `ptr`, so the value will be
+    /// dropped along with the box.
+    ///
+    /// # Undefined Behaviour
+    ///
+    /// * The storage must hold a valid `T`.
+    pub unsafe fn assume_init(&mut self) {
+        self.init = true;
+    }
+
+    /// Moves the value out, leaving the storage uninitialized. Returns `None` if it was
+    /// not initialized.
+    pub fn take(&mut self) -> Option<T> {
+        if self.init {
+            self.init = false;
+            unsafe { Some(ptr::read(self.ptr())) }
+        } else {
+            None
+        }
+    }
+
+    /// Gets a reference to the value, if initialized.
+    pub fn get(&self) -> Option<&T> {
+        if self.init { unsafe { Some(&*self.ptr()) } } else { None }
+    }
+
+    /// Gets a mutable reference to the value, if initialized.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.init { unsafe { Some(&mut *self.ptr()) } } else { None }
+    }
+}
+
+impl<T> Drop for RawBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            if self.init {
+                ptr::drop_in_place(self.ptr());
+            }
+            lazy::dealloc(self.ptr());
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::panic;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_init() {
+        let mut b = RawBox::<u32>::new_uninit();
+        assert!(!b.is_init());
+        assert_eq!(b.get(), None);
+        b.write(5);
+        assert_eq!(b.get(), Some(&5));
+        *b.get_mut().unwrap() += 1;
+        assert_eq!(b.take(), Some(6));
+        assert_eq!(b.take(), None);
+
+        unsafe {
+            b.ptr().write(3);
+            b.assume_init();
+        }
+        assert_eq!(b.get(), Some(&3));
+    }
+
+    #[test]
+    fn test_drop() {
+        let rc = Rc::new(());
+        let mut b = RawBox::new_uninit();
+        drop(RawBox::<Rc<()>>::new_uninit());
+        b.write(rc.clone());
+        assert_eq!(Rc::strong_count(&rc), 2);
+        b.write(rc.clone());
+        assert_eq!(Rc::strong_count(&rc), 2);
+        drop(b);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn test_write_panicking_drop() {
+        struct PanicOnDrop(Rc<Cell<usize>>, bool);
+
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+                if self.1 {
+                    panic!("drop");
+                }
+            }
+        }
+
+        let old = Rc::new(Cell::new(0));
+        let new = Rc::new(Cell::new(0));
+        let mut b = RawBox::new(PanicOnDrop(old.clone(), true));
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            b.write(PanicOnDrop(new.clone(), false));
+        }));
+        assert!(result.is_err());
+        assert_eq!((old.get(), new.get()), (1, 1));
+        assert!(!b.is_init());
+        drop(b);
+        assert_eq!((old.get(), new.get()), (1, 1));
+    }
+
+    #[test]
+    fn test_raw() {
+        let rc = Rc::new(());
+        let ptr = RawBox::new(rc.clone()).into_raw();
+        assert_eq!(Rc::strong_count(&rc), 2);
+        let b = unsafe { RawBox::from_raw(ptr, true) };
+        drop(b);
+        assert_eq!(Rc::strong_count(&rc), 1);
+
+        let ptr = RawBox::<u8>::new_uninit().into_raw();
+        unsafe { lazy::dealloc(ptr); }
+    }
+
+    #[test]
+    fn test_zst() {
+        let mut b = RawBox::<()>::new_uninit();
+        assert!(!b.ptr().is_null());
+        b.write(());
+        assert_eq!(b.take(), Some(()));
+    }
+}
@@ -0,0 +1,191 @@
+use std::cmp;
+use std::mem::size_of;
+use std::ptr::{self, NonNull};
+use crate::alloc::lazy;
+
+/// The smallest non-zero capacity `reserve` will grow a buffer to.
+const MIN_CAP: usize = 4;
+
+/// An owned, uninitialized buffer of `T`s: a pointer and a capacity.
+///
+/// `RawBuf` handles allocation, growth and deallocation of its buffer, but never reads, writes
+/// or drops any `T`. Tracking which elements are initialized is left to the user. Zero-sized types
+/// never allocate, and always report a capacity of `usize::MAX`.
+///
+/// Allocation goes through `alloc::lazy`, so running out of memory invokes `alloc::oom`, and
+/// requesting more than `isize::MAX` bytes panics.
+pub struct RawBuf<T> {
+    ptr: NonNull<T>,
+    cap: usize,
+}
+
+unsafe impl<T: Send> Send for RawBuf<T> {}
+unsafe impl<T: Sync> Sync for RawBuf<T> {}
+
+impl<T> RawBuf<T> {
+    /// Makes a new buffer without allocating.
+    pub fn new() -> RawBuf<T> {
+        let cap = if size_of::<T>() == 0 { usize::MAX } else { 0 };
+        RawBuf { ptr: NonNull::dangling(), cap }
+    }
+
+    /// Makes a new buffer with room for exactly `cap` elements.
+    pub fn with_capacity(cap: usize) -> RawBuf<T> {
+        let mut buf = RawBuf::new();
+        buf.reserve_exact(0, cap);
+        buf
+    }
+
+    /// Gets a pointer to the start of the buffer. This is a non-null dangling pointer if nothing
+    /// has been allocated.
+    pub fn ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Gets the number of elements the buffer can hold.
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// Gets the whole buffer as a raw slice, for use with `RawMutSlice`.
+    pub fn as_raw_mut_slice(&self) -> *mut [T] {
+        ptr::slice_from_raw_parts_mut(self.ptr(), self.cap)
+    }
+
+    /// Ensures the buffer can hold at least `used + additional` elements, growing it to at least
+    /// double its current capacity if it needs to grow at all. The first `used` elements are
+    /// preserved if the buffer moves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `used + additional` overflows.
+    pub fn reserve(&mut self, used: usize, additional: usize) {
+        let required = self.required_cap(used, additional);
+        if required > self.cap {
+            let new_cap = cmp::max(cmp::max(self.cap * 2, required), MIN_CAP);
+            unsafe { self.resize(new_cap) }
+        }
+    }
+
+    /// Ensures the buffer can hold at least `used + additional` elements, growing it to exactly
+    /// that capacity if it needs to grow at all. The first `used` elements are preserved if the
+    /// buffer moves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `used + additional` overflows.
+    pub fn reserve_exact(&mut self, used: usize, additional: usize) {
+        let required = self.required_cap(used, additional);
+        if required > self.cap {
+            unsafe { self.resize(required) }
+        }
+    }
+
+    /// Shrinks the buffer to hold exactly `cap` elements, freeing it entirely if `cap` is 0. Does
+    /// nothing if the buffer is already no larger than `cap`. The first `cap` elements are
+    /// preserved if the buffer moves.
+    pub fn shrink_to(&mut self, cap: usize) {
+        if cap < self.cap {
+            unsafe { self.resize(cap) }
+        }
+    }
+
+    fn required_cap(&self, used: usize, additional: usize) -> usize {
+        debug_assert!(used <= self.cap, "used larger than capacity in RawBuf::reserve");
+        used.checked_add(additional).expect("capacity overflow")
+    }
+
+    /// Moves the buffer to an allocation of exactly `cap` elements.
+    unsafe fn resize(&mut self, cap: usize) {
+        if size_of::<T>() == 0 {
+            return;
+        }
+        if cap == 0 {
+            lazy::dealloc_array(self.ptr(), self.cap);
+            self.ptr = NonNull::dangling();
+        } else if self.cap == 0 {
+            self.ptr = NonNull::new_unchecked(lazy::alloc_array(cap));
+        } else {
+            self.ptr = NonNull::new_unchecked(lazy::realloc_array(self.ptr(), self.cap, cap));
+        }
+        self.cap = cap;
+    }
+}
+
+impl<T> Default for RawBuf<T> {
+    fn default() -> RawBuf<T> {
+        RawBuf::new()
+    }
+}
+
+impl<T> Drop for RawBuf<T> {
+    fn drop(&mut self) {
+        if size_of::<T>() != 0 && self.cap != 0 {
+            unsafe { lazy::dealloc_array(self.ptr(), self.cap); }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rawslice::{RawSlice, RawMutSlice};
+
+    #[test]
+    fn test_reserve() {
+        let mut buf = RawBuf::<u32>::new();
+        assert_eq!(buf.cap(), 0);
+        buf.reserve(0, 1);
+        assert_eq!(buf.cap(), 4);
+        unsafe {
+            for i in 0..4 { RawMutSlice::write(buf.as_raw_mut_slice(), i, i as u32); }
+        }
+        buf.reserve(4, 1);
+        assert_eq!(buf.cap(), 8);
+        buf.reserve(4, 4);
+        assert_eq!(buf.cap(), 8);
+        buf.reserve(8, 100);
+        assert_eq!(buf.cap(), 108);
+        unsafe {
+            for i in 0..4 { assert_eq!(RawSlice::read(buf.as_raw_mut_slice(), i), i as u32); }
+        }
+    }
+
+    #[test]
+    fn test_reserve_exact_shrink() {
+        let mut buf = RawBuf::<u64>::with_capacity(3);
+        assert_eq!(buf.cap(), 3);
+        unsafe { RawMutSlice::write(buf.as_raw_mut_slice(), 1, 7); }
+        buf.reserve_exact(3, 2);
+        assert_eq!(buf.cap(), 5);
+        assert_eq!(buf.as_raw_mut_slice().len(), 5);
+        buf.shrink_to(10);
+        assert_eq!(buf.cap(), 5);
+        buf.shrink_to(2);
+        assert_eq!(buf.cap(), 2);
+        unsafe { assert_eq!(RawSlice::read(buf.as_raw_mut_slice(), 1), 7); }
+        buf.shrink_to(0);
+        assert_eq!(buf.cap(), 0);
+        buf.reserve(0, 1);
+        assert_eq!(buf.cap(), 4);
+    }
+
+    #[test]
+    fn test_zst() {
+        let mut buf = RawBuf::<()>::with_capacity(10);
+        assert_eq!(buf.cap(), usize::MAX);
+        buf.reserve(usize::MAX, 0);
+        buf.shrink_to(0);
+        assert_eq!(buf.cap(), usize::MAX);
+        assert!(!buf.ptr().is_null());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_overflow() {
+        let mut buf = RawBuf::<u8>::with_capacity(4);
+        buf.reserve(4, usize::MAX);
+    }
+}
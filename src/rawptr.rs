@@ -8,12 +8,17 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::mem;
+use std::mem::{align_of, size_of};
 use std::ptr;
-use std::raw::Slice;
-use rawslice::{RawSlice, RawMutSlice};
+use crate::rawslice::{RawSlice, RawMutSlice};
 
 /// Extension trait for convenience methods on raw pointers
+///
+/// With debug assertions enabled, `add` and `sub` panic if the offset overflows `isize` or wraps
+/// around the address space, and the methods that access memory panic if a ptr is null or
+/// misaligned. Raw pointers now have inherent methods of the same names, which take precedence
+/// in method-call syntax, so these checks only apply when calling through the trait, as in
+/// `RawPtrExt::add(ptr, count)`.
 pub trait RawPtrExt<T> {
     /// Converts the pointer into a raw slice.
     fn as_raw_slice(self, len: usize) -> *const [T];
@@ -34,6 +39,13 @@ pub trait RawPtrExt<T> {
     /// Reads the value from `self` and returns it.
     unsafe fn read(self) -> T;
 
+    /// Reads the value from `self` and returns it, without requiring `self` to be aligned.
+    unsafe fn read_unaligned(self) -> T;
+
+    /// Reads the value from `self` and returns it with a volatile read, which the compiler may
+    /// not elide or reorder with respect to other volatile accesses.
+    unsafe fn read_volatile(self) -> T;
+
     /// Copies `count * size_of<T>()` many bytes from `self` to `dest`,
     /// assuming that the source and destination *may* overlap.
     unsafe fn copy(self, dest: *mut T, count: usize);
@@ -41,9 +53,16 @@ pub trait RawPtrExt<T> {
     /// Copies `count * size_of<T>()` many bytes from `self` to `dest`,
     /// assuming that the source and destination *do not* overlap.
     unsafe fn copy_nonoverlapping(self, dest: *mut T, count: usize);
+
+    /// Copies `count` values from `self` to `dest` one at a time, with a volatile read and a
+    /// volatile write for each. The source and destination *may* overlap.
+    unsafe fn copy_volatile(self, dest: *mut T, count: usize);
 }
 
 /// Extension trait for convenience methods on mutable raw pointers
+///
+/// With debug assertions enabled, the methods that access memory panic if a ptr is null or
+/// misaligned. As with `RawPtrExt`, this only applies when calling through the trait.
 pub trait RawMutPtrExt<T> {
     /// Converts the pointer into a raw mutable slice.
     fn as_raw_mut_slice(self, len: usize) -> *mut [T];
@@ -59,6 +78,13 @@ pub trait RawMutPtrExt<T> {
     /// so care must be taken to previously deallocate the value at `dst`.
     unsafe fn write(self, src: T);
 
+    /// Like `write`, but without requiring `self` to be aligned.
+    unsafe fn write_unaligned(self, src: T);
+
+    /// Like `write`, but with a volatile write, which the compiler may not elide or reorder with
+    /// respect to other volatile accesses.
+    unsafe fn write_volatile(self, src: T);
+
     /// Sets the `count * size_of<T>()` bytes at the address of this pointer to the the given
     /// byte. Good for zeroing out memory.
     unsafe fn write_bytes(self, byte: u8, count: usize);
@@ -74,12 +100,7 @@ pub trait RawMutPtrExt<T> {
 
 impl<T> RawPtrExt<T> for *const T {
     fn as_raw_slice(self, len: usize) -> *const [T] {
-        unsafe {
-            mem::transmute(Slice {
-                data: self,
-                len: len
-            })
-        }
+        ptr::slice_from_raw_parts(self, len)
     }
 
     unsafe fn as_slice<'a>(self, len: usize) -> &'a [T] {
@@ -87,24 +108,53 @@ impl<T> RawPtrExt<T> for *const T {
     }
 
     unsafe fn read(self) -> T {
+        check_access("read", self);
         ptr::read(self)
     }
 
+    unsafe fn read_unaligned(self) -> T {
+        check_non_null("read_unaligned", self);
+        ptr::read_unaligned(self)
+    }
+
+    unsafe fn read_volatile(self) -> T {
+        check_access("read_volatile", self);
+        ptr::read_volatile(self)
+    }
+
     unsafe fn add(self, count: usize) -> Self {
-        self.offset(count as isize)
+        check_offset("add", self, count, false);
+        <*const T>::add(self, count)
     }
 
     unsafe fn sub(self, count: usize) -> Self {
-        self.offset(-(count as isize))
+        check_offset("sub", self, count, true);
+        <*const T>::sub(self, count)
     }
 
     unsafe fn copy(self, dest: *mut T, count: usize) {
+        check_access("copy", self);
+        check_access("copy", dest);
         ptr::copy(self, dest, count);
     }
 
     unsafe fn copy_nonoverlapping(self, dest: *mut T, count: usize) {
+        check_access("copy_nonoverlapping", self);
+        check_access("copy_nonoverlapping", dest);
         ptr::copy_nonoverlapping(self, dest, count);
     }
+
+    unsafe fn copy_volatile(self, dest: *mut T, count: usize) {
+        check_access("copy_volatile", self);
+        check_access("copy_volatile", dest);
+        let copy_one = |i| ptr::write_volatile(dest.add(i), ptr::read_volatile(self.add(i)));
+        // Copy back to front if `dest` overlaps the end of `self`, like `memmove`.
+        if (dest as usize) > (self as usize) {
+            (0..count).rev().for_each(copy_one);
+        } else {
+            (0..count).for_each(copy_one);
+        }
+    }
 }
 
 impl<T> RawPtrExt<T> for *mut T {
@@ -117,34 +167,43 @@ impl<T> RawPtrExt<T> for *mut T {
     }
 
     unsafe fn read(self) -> T {
-        ptr::read(self as *const T)
+        RawPtrExt::read(self as *const T)
+    }
+
+    unsafe fn read_unaligned(self) -> T {
+        RawPtrExt::read_unaligned(self as *const T)
+    }
+
+    unsafe fn read_volatile(self) -> T {
+        RawPtrExt::read_volatile(self as *const T)
     }
 
     unsafe fn add(self, count: usize) -> Self {
-        self.offset(count as isize)
+        check_offset("add", self, count, false);
+        <*mut T>::add(self, count)
     }
 
     unsafe fn sub(self, count: usize) -> Self {
-        self.offset(-(count as isize))
+        check_offset("sub", self, count, true);
+        <*mut T>::sub(self, count)
     }
 
     unsafe fn copy(self, dest: *mut T, count: usize) {
-        ptr::copy(self, dest, count);
+        RawPtrExt::copy(self as *const T, dest, count);
     }
 
     unsafe fn copy_nonoverlapping(self, dest: *mut T, count: usize) {
-        ptr::copy_nonoverlapping(self, dest, count);
+        RawPtrExt::copy_nonoverlapping(self as *const T, dest, count);
+    }
+
+    unsafe fn copy_volatile(self, dest: *mut T, count: usize) {
+        RawPtrExt::copy_volatile(self as *const T, dest, count);
     }
 }
 
 impl<T> RawMutPtrExt<T> for *mut T {
     fn as_raw_mut_slice(self, len: usize) -> *mut [T] {
-        unsafe {
-            mem::transmute(Slice {
-                data: self as *const T,
-                len: len
-            })
-        }
+        ptr::slice_from_raw_parts_mut(self, len)
     }
 
     unsafe fn as_mut_slice<'a>(self, len: usize) -> &'a mut [T] {
@@ -152,22 +211,77 @@ impl<T> RawMutPtrExt<T> for *mut T {
     }
 
     unsafe fn write(self, src: T) {
+        check_access("write", self);
         ptr::write(self, src);
     }
 
+    unsafe fn write_unaligned(self, src: T) {
+        check_non_null("write_unaligned", self);
+        ptr::write_unaligned(self, src);
+    }
+
+    unsafe fn write_volatile(self, src: T) {
+        check_access("write_volatile", self);
+        ptr::write_volatile(self, src);
+    }
+
     unsafe fn write_bytes(self, byte: u8, count: usize) {
+        check_access("write_bytes", self);
         ptr::write_bytes(self, byte, count);
     }
 
     unsafe fn swap(self, y: *mut T) {
+        check_access("swap", self);
+        check_access("swap", y);
         ptr::swap(self, y);
     }
 
     unsafe fn replace(self, src: T) -> T {
+        check_access("replace", self);
         ptr::replace(self, src)
     }
 }
 
+/// With debug assertions enabled, checks that offsetting `ptr` by `count` elements, backwards if
+/// `backwards` is set, neither overflows `isize` nor wraps around the address space. `op` names
+/// the method being checked.
+#[inline]
+fn check_offset<T>(op: &str, ptr: *const T, count: usize, backwards: bool) {
+    if cfg!(debug_assertions) {
+        let bytes = match count.checked_mul(size_of::<T>()) {
+            Some(bytes) if bytes <= isize::MAX as usize => bytes,
+            _ => panic!("{}: offset of {} elements of size {} from ptr {:p} overflows isize",
+                        op, count, size_of::<T>(), ptr),
+        };
+        let addr = ptr as usize;
+        let wrapped = if backwards {
+            addr.checked_sub(bytes).is_none()
+        } else {
+            addr.checked_add(bytes).is_none()
+        };
+        if wrapped {
+            panic!("{}: offset of {} bytes from ptr {:p} wraps around the address space",
+                   op, bytes, ptr);
+        }
+    }
+}
+
+/// With debug assertions enabled, checks that `ptr` is non-null. `op` names the method being
+/// checked.
+#[inline]
+fn check_non_null<T>(op: &str, ptr: *const T) {
+    debug_assert!(!ptr.is_null(), "{}: null ptr", op);
+}
+
+/// With debug assertions enabled, checks that `ptr` is non-null and aligned for T. `op` names the
+/// method being checked.
+#[inline]
+fn check_access<T>(op: &str, ptr: *const T) {
+    check_non_null(op, ptr);
+    debug_assert!((ptr as usize).is_multiple_of(align_of::<T>()),
+                  "{}: ptr {:p} is not aligned to {}", op, ptr, align_of::<T>());
+}
+
 
 
 
@@ -181,25 +295,39 @@ mod test {
             let mut x = [1,2,3,4];
             let y = x.as_ptr();
             assert_eq!(*y, 1);
-            assert_eq!(*y.add(2), 3);
-            assert_eq!(*y.add(2).sub(1), 2);
+            assert_eq!(*RawPtrExt::add(y, 2), 3);
+            assert_eq!(*RawPtrExt::sub(RawPtrExt::add(y, 2), 1), 2);
 
             let y = x.as_mut_ptr();
             assert_eq!(*y, 1);
-            assert_eq!(*y.add(2), 3);
-            assert_eq!(*y.add(2).sub(1), 2);
+            assert_eq!(*RawPtrExt::add(y, 2), 3);
+            assert_eq!(*RawPtrExt::sub(RawPtrExt::add(y, 2), 1), 2);
         }
     }
 
+    #[test]
+    fn test_raw_slice() {
+        let mut x = [1,2,3,4];
+        let y = x.as_ptr().as_raw_slice(3);
+        unsafe { assert_eq!(y.as_ref().unwrap(), &[1,2,3]); }
+
+        let y = x.as_mut_ptr().as_raw_mut_slice(2);
+        unsafe {
+            y.as_mut().unwrap()[1] = 5;
+            assert_eq!(y.as_ref().unwrap(), &[1,5]);
+        }
+        assert_eq!(x, [1,5,3,4]);
+    }
+
     #[test]
     fn test_read_write() {
         unsafe {
-            let x = &mut 1 as *mut _;
-            assert_eq!(x.read(), 1);
-            x.write(2);
-            assert_eq!(x.read(), 2);
-            x.write_bytes(0, 1);
-            assert_eq!(x.read(), 0);
+            let x = &mut 1 as *mut i32;
+            assert_eq!(RawPtrExt::read(x), 1);
+            RawMutPtrExt::write(x, 2);
+            assert_eq!(RawPtrExt::read(x), 2);
+            RawMutPtrExt::write_bytes(x, 0, 1);
+            assert_eq!(RawPtrExt::read(x), 0);
         }
     }
 
@@ -211,24 +339,97 @@ mod test {
             let xptr = x.as_mut_ptr();
             let yptr = y.as_ptr();
 
-            xptr.add(1).copy(xptr, 2);
+            RawPtrExt::copy(RawPtrExt::add(xptr, 1), xptr, 2);
             assert_eq!(x, [2,3,3,4]);
-            yptr.copy_nonoverlapping(xptr, 4);
+            RawPtrExt::copy_nonoverlapping(yptr, xptr, 4);
             assert_eq!(x, y);
         }
     }
 
+    // The checks are only compiled in with debug assertions.
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_debug_checks() {
+        use crate::alloc::test::panic_message;
+
+        let x = [0u32; 4];
+        let p = x.as_ptr();
+        unsafe {
+            let msg = panic_message(|| { RawPtrExt::add(p, usize::MAX / 2); });
+            assert_eq!(msg, format!("add: offset of {} elements of size 4 from ptr {:p} \
+                                     overflows isize", usize::MAX / 2, p));
+            let high = usize::MAX as *const u8;
+            let msg = panic_message(|| { RawPtrExt::add(high, 1); });
+            assert_eq!(msg, format!("add: offset of 1 bytes from ptr {:p} wraps around the \
+                                     address space", high));
+            let msg = panic_message(|| { RawPtrExt::sub(p as *mut u32, usize::MAX / 8); });
+            assert!(msg.starts_with("sub: offset of"), "{}", msg);
+            assert_eq!(RawPtrExt::sub(RawPtrExt::add(p, 4), 4), p);
+
+            let msg = panic_message(|| { RawPtrExt::read(ptr::null::<u32>()); });
+            assert_eq!(msg, "read: null ptr");
+            let odd = (p as *const u8).add(1) as *mut u32;
+            let msg = panic_message(|| RawMutPtrExt::write(odd, 1));
+            assert_eq!(msg, format!("write: ptr {:p} is not aligned to 4", odd));
+            let msg = panic_message(|| RawPtrExt::copy(p, odd, 1));
+            assert_eq!(msg, format!("copy: ptr {:p} is not aligned to 4", odd));
+        }
+    }
+
+    #[test]
+    fn test_unaligned() {
+        unsafe {
+            // Offsetting an 8-aligned buffer by one byte misaligns every larger type.
+            let mut buf = [0u64; 2];
+            let bytes = buf.as_mut_ptr() as *mut u8;
+            let field = bytes.add(1) as *mut u32;
+            RawMutPtrExt::write_unaligned(field, 0x0403_0201u32.to_le());
+            assert_eq!(*(bytes as *const [u8; 6]), [0, 1, 2, 3, 4, 0]);
+            assert_eq!(u32::from_le(RawPtrExt::read_unaligned(field)), 0x0403_0201);
+            let half = bytes.add(3) as *const u16;
+            assert_eq!(u16::from_le(RawPtrExt::read_unaligned(half)), 0x0403);
+
+            let fields = ptr::slice_from_raw_parts_mut(bytes.add(3) as *mut u16, 3);
+            RawMutSlice::write_unaligned(fields, 2, 0x0807u16.to_le());
+            assert_eq!(*(bytes.add(7) as *const [u8; 2]), [7, 8]);
+            assert_eq!(u16::from_le(RawSlice::read_unaligned(fields, 2)), 0x0807);
+            assert_eq!(u16::from_le(RawSlice::read_unaligned(fields, 0)), 0x0403);
+        }
+    }
+
+    #[test]
+    fn test_volatile() {
+        unsafe {
+            let mut x = [1, 2, 3, 4, 5];
+            let p = x.as_mut_ptr();
+            RawMutPtrExt::write_volatile(p.add(1), 7);
+            assert_eq!(RawPtrExt::read_volatile(p.add(1)), 7);
+
+            // Overlapping copies work in both directions.
+            RawPtrExt::copy_volatile(p, p.add(1), 3);
+            assert_eq!(x, [1, 1, 7, 3, 5]);
+            RawPtrExt::copy_volatile(p.add(2), p, 3);
+            assert_eq!(x, [7, 3, 5, 3, 5]);
+
+            let s = ptr::slice_from_raw_parts_mut(p, 5);
+            RawMutSlice::write_volatile(s, 4, 9);
+            assert_eq!(RawSlice::read_volatile(s, 4), 9);
+            RawMutSlice::copy_volatile(s.slice_from(1), s.slice_to(3));
+            assert_eq!(x, [7, 7, 3, 5, 9]);
+        }
+    }
+
     #[test]
     fn test_swap_replace() {
         unsafe {
-            let x = &mut 1 as *mut _;
+            let x = &mut 1 as *mut i32;
             let y = &mut 2;
-            x.swap(y);
+            RawMutPtrExt::swap(x, y);
             assert_eq!(*x, 2);
             assert_eq!(*y, 1);
 
 
-            x.replace(3);
+            assert_eq!(RawMutPtrExt::replace(x, 3), 2);
             assert_eq!(*x, 3);
         }
     }
@@ -8,9 +8,16 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use rawptr::{RawPtrExt, RawMutPtrExt};
+use std::mem;
+use crate::alloc::poison::ALLOC_POISON;
+use crate::rawptr::{RawPtrExt, RawMutPtrExt};
 
 /// Extension trait for non-mutating operations on raw slices.
+///
+/// Raw pointers now have inherent `read`, `write` and `write_bytes` methods, and unaligned and
+/// volatile variants of them, which take precedence over the ones here in method-call syntax even
+/// though they can't be used on slices. Call these as `RawSlice::read(slice, index)` and
+/// `RawMutSlice::write(slice, index, val)` instead.
 pub trait RawSlice<T>: Copy + Sized {
     /// Converts the rawslice into a slice.
     unsafe fn as_slice<'a>(self) -> &'a [T];
@@ -28,12 +35,22 @@ pub trait RawSlice<T>: Copy + Sized {
     /// Reads the data at the given index and interprets it as a value of T.
     /// This does not move the value out, and ignores the length of the raw slice.
     unsafe fn read(self, index: usize) -> T {
-        self.as_ptr().add(index).read()
+        RawPtrExt::read(RawPtrExt::add(self.as_ptr(), index))
+    }
+
+    /// Like `read`, but without requiring the element to be aligned.
+    unsafe fn read_unaligned(self, index: usize) -> T {
+        RawPtrExt::read_unaligned(RawPtrExt::add(self.as_ptr(), index))
+    }
+
+    /// Like `read`, but with a volatile read.
+    unsafe fn read_volatile(self, index: usize) -> T {
+        RawPtrExt::read_volatile(RawPtrExt::add(self.as_ptr(), index))
     }
 
     /// Gets a reference to the element at the given index.
     unsafe fn get<'a>(self, index: usize) -> &'a T {
-        &*self.as_ptr().add(index)
+        &*RawPtrExt::add(self.as_ptr(), index)
     }
 
     /// Gets a subslice of this one.
@@ -48,6 +65,14 @@ pub trait RawSlice<T>: Copy + Sized {
     unsafe fn slice_from(self, from: usize) -> Self {
         self.slice(from, self.len())
     }
+
+    /// Checks whether every byte of the slice is `alloc::poison::ALLOC_POISON`, which, with the
+    /// `debug-poison` feature, means it has not been written to since it was allocated.
+    unsafe fn is_poisoned(self) -> bool {
+        let bytes = self.as_ptr() as *const u8;
+        let len = self.len() * mem::size_of::<T>();
+        (0..len).all(|i| RawPtrExt::read(RawPtrExt::add(bytes, i)) == ALLOC_POISON)
+    }
 }
 
 
@@ -64,6 +89,12 @@ pub trait RawMutSlice<T> : RawSlice<T> + Sized {
     /// Ignores the length of the raw slice.
     unsafe fn write(self, index: usize, val: T);
 
+    /// Like `write`, but without requiring the element to be aligned.
+    unsafe fn write_unaligned(self, index: usize, val: T);
+
+    /// Like `write`, but with a volatile write.
+    unsafe fn write_volatile(self, index: usize, val: T);
+
     /// Sets every byte in the slice to to the given one, without reading or destroying whatever
     /// data might have been contained. Can be used to zero memory out.
     unsafe fn write_bytes(self, byte: u8);
@@ -78,6 +109,11 @@ pub trait RawMutSlice<T> : RawSlice<T> + Sized {
     /// not consider the target's length.
     unsafe fn copy_nonoverlapping(self, from: *const[T]);
 
+    /// Copies the contents of the given rawslice into this one one element at a time, with
+    /// volatile reads and writes. The two might overlap. Uses `from.len()` to determine the
+    /// length of the copied data, but does not consider the target's length.
+    unsafe fn copy_volatile(self, from: *const[T]);
+
     /// Gets a mutable reference to the value at the given index.
     unsafe fn get_mut<'a>(self, index: usize) -> &'a mut T;
 }
@@ -109,8 +145,16 @@ impl<T> RawSlice<T> for *const [T] {
         &*self
     }
 
+    fn len(self) -> usize {
+        <*const [T]>::len(self)
+    }
+
+    fn as_ptr(self) -> *const T {
+        self as *const T
+    }
+
     unsafe fn slice(self, from: usize, to: usize) -> *const [T] {
-        self.as_ptr().add(from).as_raw_slice(to - from)
+        RawPtrExt::add(RawSlice::as_ptr(self), from).as_raw_slice(to - from)
     }
 }
 
@@ -119,8 +163,16 @@ impl<T> RawSlice<T> for *mut [T] {
         &*self
     }
 
+    fn len(self) -> usize {
+        <*mut [T]>::len(self)
+    }
+
+    fn as_ptr(self) -> *const T {
+        self as *const T
+    }
+
     unsafe fn slice(self, from: usize, to: usize) -> *mut [T] {
-        self.as_mut_ptr().add(from).as_raw_mut_slice(to - from)
+        RawPtrExt::add(RawMutSlice::as_mut_ptr(self), from).as_raw_mut_slice(to - from)
     }
 }
 
@@ -130,27 +182,92 @@ impl<T> RawMutSlice<T> for *mut [T] {
     }
 
     fn as_mut_ptr(self) -> *mut T {
-        unsafe { self.as_mut_slice().as_mut_ptr() }
+        self as *mut T
     }
 
     unsafe fn write(self, index: usize, val: T) {
-        self.as_mut_ptr().add(index).write(val);
+        RawMutPtrExt::write(RawPtrExt::add(RawMutSlice::as_mut_ptr(self), index), val);
+    }
+
+    unsafe fn write_unaligned(self, index: usize, val: T) {
+        RawMutPtrExt::write_unaligned(RawPtrExt::add(RawMutSlice::as_mut_ptr(self), index), val);
+    }
+
+    unsafe fn write_volatile(self, index: usize, val: T) {
+        RawMutPtrExt::write_volatile(RawPtrExt::add(RawMutSlice::as_mut_ptr(self), index), val);
     }
 
     unsafe fn write_bytes(self, byte: u8) {
         let len = self.len();
-        self.as_mut_ptr().write_bytes(byte, len);
+        RawMutPtrExt::write_bytes(RawMutSlice::as_mut_ptr(self), byte, len);
     }
 
     unsafe fn copy(self, from: *const[T]) {
-        from.as_ptr().copy(self.as_mut_ptr(), from.len());
+        RawPtrExt::copy(RawSlice::as_ptr(from), RawMutSlice::as_mut_ptr(self), from.len());
     }
 
     unsafe fn copy_nonoverlapping(self, from: *const[T]) {
-        from.as_ptr().copy_nonoverlapping(self.as_mut_ptr(), from.len());
+        RawPtrExt::copy_nonoverlapping(RawSlice::as_ptr(from), RawMutSlice::as_mut_ptr(self),
+                                       from.len());
+    }
+
+    unsafe fn copy_volatile(self, from: *const[T]) {
+        RawPtrExt::copy_volatile(RawSlice::as_ptr(from), RawMutSlice::as_mut_ptr(self), from.len());
     }
 
     unsafe fn get_mut<'a>(self, index: usize) -> &'a mut T {
-        &mut *self.as_mut_ptr().add(index)
+        &mut *RawPtrExt::add(RawMutSlice::as_mut_ptr(self), index)
+    }
+}
+
+
+
+// The checks are only compiled in with debug assertions.
+#[cfg(all(test, debug_assertions))]
+mod test {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn test_debug_checks() {
+        use crate::alloc::test::panic_message;
+
+        let mut x = [0u32; 4];
+        let p = x.as_mut_ptr();
+        let odd = unsafe { (p as *mut u8).add(1) as *mut u32 };
+        let null = ptr::slice_from_raw_parts_mut(ptr::null_mut::<u32>(), 4);
+        let unaligned = ptr::slice_from_raw_parts_mut(odd, 2);
+        let s = ptr::slice_from_raw_parts_mut(p, 4);
+        let far = usize::MAX / 2;
+        let overflow = format!("add: offset of {} elements of size 4 from ptr {:p} overflows \
+                                isize", far, p);
+        let misaligned = |op| format!("{}: ptr {:p} is not aligned to 4", op, odd);
+        unsafe {
+            assert_eq!(panic_message(|| { RawSlice::read(null, 0); }), "read: null ptr");
+            assert_eq!(panic_message(|| { RawSlice::read_unaligned(null, 0); }),
+                       "read_unaligned: null ptr");
+            assert_eq!(panic_message(|| { RawSlice::read_volatile(unaligned, 0); }),
+                       misaligned("read_volatile"));
+            assert_eq!(panic_message(|| { RawSlice::get(s, far); }), overflow);
+            assert_eq!(panic_message(|| { RawSlice::slice(s, far, far); }), overflow);
+            assert_eq!(panic_message(|| { RawSlice::slice_from(s, far); }), overflow);
+            assert_eq!(panic_message(|| { RawSlice::is_poisoned(null); }), "read: null ptr");
+
+            assert_eq!(panic_message(|| RawMutSlice::write(unaligned, 0, 1)),
+                       misaligned("write"));
+            assert_eq!(panic_message(|| RawMutSlice::write_unaligned(null, 0, 1)),
+                       "write_unaligned: null ptr");
+            assert_eq!(panic_message(|| RawMutSlice::write_volatile(null, 0, 1)),
+                       "write_volatile: null ptr");
+            assert_eq!(panic_message(|| RawMutSlice::write_bytes(unaligned, 0)),
+                       misaligned("write_bytes"));
+            assert_eq!(panic_message(|| RawMutSlice::copy(unaligned, s.slice_to(2))),
+                       misaligned("copy"));
+            assert_eq!(panic_message(|| RawMutSlice::copy_nonoverlapping(s, null.slice_to(1))),
+                       "copy_nonoverlapping: null ptr");
+            assert_eq!(panic_message(|| RawMutSlice::copy_volatile(unaligned, s.slice_to(2))),
+                       misaligned("copy_volatile"));
+            assert_eq!(panic_message(|| { RawMutSlice::get_mut(s, far); }), overflow);
+        }
     }
 }
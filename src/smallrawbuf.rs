@@ -0,0 +1,223 @@
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::mem::{size_of, MaybeUninit};
+use std::ptr::{self, NonNull};
+use crate::alloc::lazy;
+
+/// An owned, uninitialized buffer of `T`s that holds up to `N` elements inline, and moves to the
+/// heap when it needs more.
+///
+/// It otherwise behaves like `RawBuf`, and allocates the same way, so requesting more than
+/// `isize::MAX` bytes panics.
+///
+/// While the buffer is inline, its ptr points into the `SmallRawBuf` itself, and so is invalidated
+/// whenever the `SmallRawBuf` is moved. Get a fresh one with `ptr` or `as_raw_mut_slice` after any
+/// move.
+pub struct SmallRawBuf<T, const N: usize> {
+    inline: UnsafeCell<MaybeUninit<[T; N]>>,
+    /// The heap buffer, which is only allocated when `cap > N`.
+    heap: NonNull<T>,
+    cap: usize,
+}
+
+unsafe impl<T: Send, const N: usize> Send for SmallRawBuf<T, N> {}
+unsafe impl<T: Sync, const N: usize> Sync for SmallRawBuf<T, N> {}
+
+impl<T, const N: usize> SmallRawBuf<T, N> {
+    /// Makes a new inline buffer, with a capacity of `N`.
+    pub fn new() -> SmallRawBuf<T, N> {
+        let cap = if size_of::<T>() == 0 { usize::MAX } else { N };
+        SmallRawBuf {
+            inline: UnsafeCell::new(MaybeUninit::uninit()),
+            heap: NonNull::dangling(),
+            cap,
+        }
+    }
+
+    /// Makes a new buffer with room for at least `cap` elements: exactly `cap` if that is more
+    /// than `N`, and `N` otherwise.
+    pub fn with_capacity(cap: usize) -> SmallRawBuf<T, N> {
+        let mut buf = SmallRawBuf::new();
+        buf.reserve_exact(0, cap);
+        buf
+    }
+
+    /// Gets a pointer to the start of the buffer, which is inside `self` while the buffer is
+    /// inline.
+    pub fn ptr(&self) -> *mut T {
+        if self.spilled() {
+            self.heap.as_ptr()
+        } else {
+            self.inline.get() as *mut T
+        }
+    }
+
+    /// Gets the number of elements the buffer can hold.
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// Checks whether the buffer has moved to the heap.
+    pub fn spilled(&self) -> bool {
+        size_of::<T>() != 0 && self.cap > N
+    }
+
+    /// Gets the whole buffer as a raw slice, for use with `RawMutSlice`.
+    pub fn as_raw_mut_slice(&self) -> *mut [T] {
+        ptr::slice_from_raw_parts_mut(self.ptr(), self.cap)
+    }
+
+    /// Ensures the buffer can hold at least `used + additional` elements, growing it to at least
+    /// double its current capacity if it needs to grow at all. The first `used` elements are
+    /// preserved if the buffer moves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `used + additional` overflows.
+    pub fn reserve(&mut self, used: usize, additional: usize) {
+        let required = self.required_cap(used, additional);
+        if required > self.cap {
+            let new_cap = cmp::max(self.cap * 2, required);
+            unsafe { self.resize(used, new_cap) }
+        }
+    }
+
+    /// Ensures the buffer can hold at least `used + additional` elements, growing it to exactly
+    /// that capacity if it needs to grow at all. The first `used` elements are preserved if the
+    /// buffer moves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `used + additional` overflows.
+    pub fn reserve_exact(&mut self, used: usize, additional: usize) {
+        let required = self.required_cap(used, additional);
+        if required > self.cap {
+            unsafe { self.resize(used, required) }
+        }
+    }
+
+    /// Shrinks the buffer to hold exactly `cap` elements, moving it back inline if `cap` is no
+    /// more than `N`. Does nothing if the buffer is already no larger than `cap`, or is inline.
+    /// The first `cap` elements are preserved if the buffer moves.
+    pub fn shrink_to(&mut self, cap: usize) {
+        if self.spilled() && cap < self.cap {
+            unsafe { self.resize(cap, cmp::max(cap, N)) }
+        }
+    }
+
+    fn required_cap(&self, used: usize, additional: usize) -> usize {
+        debug_assert!(used <= self.cap, "used larger than capacity in SmallRawBuf::reserve");
+        used.checked_add(additional).expect("capacity overflow")
+    }
+
+    /// Moves the buffer to storage for exactly `cap` elements, inline if `cap` is `N`, preserving
+    /// the first `used`.
+    unsafe fn resize(&mut self, used: usize, cap: usize) {
+        if size_of::<T>() == 0 {
+            return;
+        }
+        if cap == N {
+            ptr::copy_nonoverlapping(self.heap.as_ptr(), self.inline.get() as *mut T, used);
+            lazy::dealloc_array(self.heap.as_ptr(), self.cap);
+            self.heap = NonNull::dangling();
+        } else if !self.spilled() {
+            let heap = lazy::alloc_array(cap);
+            ptr::copy_nonoverlapping(self.inline.get() as *mut T, heap, used);
+            self.heap = NonNull::new_unchecked(heap);
+        } else {
+            self.heap = NonNull::new_unchecked(lazy::realloc_array(self.heap.as_ptr(), self.cap,
+                                                                   cap));
+        }
+        self.cap = cap;
+    }
+}
+
+impl<T, const N: usize> Default for SmallRawBuf<T, N> {
+    fn default() -> SmallRawBuf<T, N> {
+        SmallRawBuf::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SmallRawBuf<T, N> {
+    fn drop(&mut self) {
+        if self.spilled() {
+            unsafe { lazy::dealloc_array(self.heap.as_ptr(), self.cap); }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rawslice::{RawSlice, RawMutSlice};
+
+    #[test]
+    fn test_inline() {
+        let buf = SmallRawBuf::<u32, 8>::with_capacity(5);
+        assert_eq!(buf.cap(), 8);
+        assert!(!buf.spilled());
+        let start = &buf as *const _ as usize;
+        assert!((start..start + size_of::<SmallRawBuf<u32, 8>>()).contains(&(buf.ptr() as usize)));
+        unsafe {
+            for i in 0..8 { RawMutSlice::write(buf.as_raw_mut_slice(), i, i as u32); }
+            for i in 0..8 { assert_eq!(RawSlice::read(buf.as_raw_mut_slice(), i), i as u32); }
+        }
+    }
+
+    #[test]
+    fn test_spill_and_return() {
+        let mut buf = SmallRawBuf::<u64, 4>::new();
+        unsafe {
+            for i in 0..4 { RawMutSlice::write(buf.as_raw_mut_slice(), i, i as u64); }
+        }
+        buf.reserve(4, 1);
+        assert!(buf.spilled());
+        assert_eq!(buf.cap(), 8);
+        buf.reserve_exact(8, 2);
+        assert_eq!(buf.cap(), 10);
+        unsafe {
+            RawMutSlice::write(buf.as_raw_mut_slice(), 9, 9);
+            for i in 0..4 { assert_eq!(RawSlice::read(buf.as_raw_mut_slice(), i), i as u64); }
+        }
+        buf.shrink_to(6);
+        assert_eq!(buf.cap(), 6);
+        buf.shrink_to(3);
+        assert!(!buf.spilled());
+        assert_eq!(buf.cap(), 4);
+        unsafe {
+            for i in 0..3 { assert_eq!(RawSlice::read(buf.as_raw_mut_slice(), i), i as u64); }
+        }
+        buf.shrink_to(0);
+        assert_eq!(buf.cap(), 4);
+    }
+
+    #[test]
+    fn test_zero_inline() {
+        let mut buf = SmallRawBuf::<u8, 0>::new();
+        assert_eq!(buf.cap(), 0);
+        buf.reserve(0, 1);
+        assert!(buf.spilled());
+        assert_eq!(buf.cap(), 1);
+        buf.shrink_to(0);
+        assert!(!buf.spilled());
+        assert_eq!(buf.cap(), 0);
+    }
+
+    #[test]
+    fn test_zst() {
+        let mut buf = SmallRawBuf::<(), 2>::with_capacity(10);
+        assert_eq!(buf.cap(), usize::MAX);
+        buf.reserve(usize::MAX, 0);
+        assert!(!buf.spilled());
+        assert!(!buf.ptr().is_null());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_overflow() {
+        let mut buf = SmallRawBuf::<u8, 4>::new();
+        buf.reserve(4, usize::MAX);
+    }
+}
@@ -8,7 +8,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use rawslice::{RawSlice, RawMutSlice, SliceRawExt};
+use crate::rawslice::{RawSlice, RawMutSlice, SliceRawExt};
 
 /// A wrapper for a slice that provides unchecked versions of the standard operations.
 pub struct UncheckedSlice<'a, T: 'a > {
@@ -44,7 +44,7 @@ impl<T> SliceUncheckedExt<T> for [T] {
 impl<'a, T> UncheckedSlice<'a, T> {
     /// Makes a new unchecked slice from a slice.
     pub fn new(slice: &'a [T]) -> UncheckedSlice<'a, T> {
-        UncheckedSlice{ slice: slice }
+        UncheckedSlice{ slice }
     }
 
     /// Gets the length of the slice.
@@ -86,7 +86,7 @@ impl<'a, T> UncheckedSlice<'a, T> {
 impl<'a, T> UncheckedMutSlice<'a, T> {
     /// Makes a new unchecked slice from a slice.
     pub fn new(slice: &'a mut [T]) -> UncheckedMutSlice<'a, T> {
-        UncheckedMutSlice{ slice: slice }
+        UncheckedMutSlice{ slice }
     }
 
     /// Gets the length of the slice.